@@ -1,65 +1,211 @@
-/// FITS Bintable Reader
-use std::{collections::HashMap, rc::Rc};
+/// FITS Bintable Reader and Writer
+mod de;
+mod hdus;
+mod ser;
+mod writer;
 
-use fitrs::{Fits, FitsData, FitsDataArray, Hdu, HeaderValue};
-use serde::de::DeserializeOwned;
-use serde_json::{json, Map, Number, Value};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    fs::File,
+    ops::Range,
+    path::Path,
+    rc::Rc,
+    slice::Chunks,
+};
 
-use anyhow::Result;
+use fitrs::{Fits, Hdu, HeaderValue};
+use itertools::Either;
+use memmap2::Mmap;
+use serde::Deserialize;
+use serde_json::{json, Number, Value};
+
+use anyhow::{ensure, Result};
 
 use crate::error::AstroError;
 
-fn datatype_size(format: &str) -> Result<usize> {
-    let count = format[..1].parse::<usize>().unwrap();
-    let datatype = format.chars().nth(1).unwrap();
-
-    if datatype == 'X' {
-        Ok((count as f32 / 8.0).ceil() as usize)
-    } else {
-        Ok(match datatype {
-            'L' => 1,  // Logical (boolean)
-            'B' => 1,  // 8-bit byte
-            'I' => 2,  // 16-bit integer
-            'J' => 4,  // 32-bit integer
-            'K' => 8,  // 64-bit integer
-            'A' => 1,  // Character
-            'E' => 4,  // Single-precision floating point (32-bit float)
-            'D' => 8,  // Double-precision floating point (64-bit float)
-            'C' => 8,  // Complex floating point (2 x 32-bit)
-            'M' => 16, // Double complex floating point (2 x 64-bit)
-            _ => Err(AstroError::new(&format!(
-                "Can't calculate size: unknown column type: {}",
-                datatype
-            )))?,
-        })
+pub use de::RowDeserializer;
+pub use hdus::{list_hdus, HduInfo, HduType};
+pub use writer::{ColumnData, ColumnDef, FitsTableWriter};
+
+const BLOCK_SIZE: usize = 2880;
+const CARD_SIZE: usize = 80;
+
+fn datatype_size(datatype: char) -> Result<usize> {
+    Ok(match datatype {
+        'L' => 1,  // Logical (boolean)
+        'X' => 1,  // Bits, packed 8 to a byte
+        'B' => 1,  // 8-bit byte
+        'I' => 2,  // 16-bit integer
+        'J' => 4,  // 32-bit integer
+        'K' => 8,  // 64-bit integer
+        'A' => 1,  // Character
+        'E' => 4,  // Single-precision floating point (32-bit float)
+        'D' => 8,  // Double-precision floating point (64-bit float)
+        'C' => 8,  // Complex floating point (2 x 32-bit)
+        'M' => 16, // Double complex floating point (2 x 64-bit)
+        'P' => 8,  // Variable-length array descriptor (2 x 32-bit)
+        'Q' => 16, // Variable-length array descriptor (2 x 64-bit)
+        _ => Err(AstroError::new(&format!(
+            "Can't calculate size: unknown column type: {}",
+            datatype
+        )))?,
+    })
+}
+
+/// A column's data format, given by a TFORMn keyword as `rTa`: an optional
+/// repeat count `r` (1 if omitted), the data type `T` and additional
+/// characters `a` whose meaning depends on the type
+#[derive(Debug, Clone, PartialEq)]
+pub struct TForm {
+    pub repeat: usize,
+    pub datatype: char,
+    pub extra: String,
+}
+
+impl TForm {
+    pub fn parse(format: &str) -> Result<Self> {
+        let format = format.trim();
+        let digits = format
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or(AstroError::new(&format!(
+                "Invalid TFORM {:?}: missing data type",
+                format
+            )))?;
+
+        let repeat: usize = if digits == 0 {
+            1
+        } else {
+            format[..digits].parse()?
+        };
+        let datatype = format[digits..].chars().next().unwrap();
+        let extra = format[digits + datatype.len_utf8()..].to_string();
+
+        ensure!(
+            repeat.checked_mul(datatype_size(datatype)?).is_some(),
+            AstroError::new(&format!(
+                "Invalid TFORM {:?}: repeat count too large",
+                format
+            ))
+        );
+
+        let tform = Self {
+            repeat,
+            datatype,
+            extra,
+        };
+
+        if let Some(heap_datatype) = tform.heap_datatype() {
+            ensure!(
+                repeat <= 1 && !matches!(heap_datatype, 'P' | 'Q'),
+                AstroError::new(&format!("Invalid TFORM {:?}", format))
+            );
+            datatype_size(heap_datatype)?;
+        }
+
+        Ok(tform)
+    }
+
+    /// Type of the elements of variable-length arrays, given as `rPt(max)` or
+    /// `rQt(max)`, whose rows hold descriptors of arrays in the heap
+    pub fn heap_datatype(&self) -> Option<char> {
+        match self.datatype {
+            'P' | 'Q' => Some(self.extra.chars().next().unwrap_or(' ')),
+            _ => None,
+        }
+    }
+
+    /// Size of a single element in bytes
+    pub fn element_size(&self) -> usize {
+        datatype_size(self.datatype).unwrap()
+    }
+
+    /// Number of bytes the column takes up in a row
+    pub fn width(&self) -> usize {
+        match self.datatype {
+            'X' => self.repeat.div_ceil(8),
+            _ => self.repeat * self.element_size(),
+        }
+    }
+
+    /// Whether each row holds a single element: strings are read whole and
+    /// up to 8 bits as a single byte, anything else with a repeat count other
+    /// than 1 as an array.
+    pub fn is_scalar(&self) -> bool {
+        match self.datatype {
+            'A' => true,
+            'P' | 'Q' => self.heap_datatype() == Some('A'),
+            'X' => (1..=8).contains(&self.repeat),
+            _ => self.repeat == 1,
+        }
+    }
+}
+
+impl Display for TForm {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.repeat != 1 {
+            write!(f, "{}", self.repeat)?;
+        }
+        write!(f, "{}{}", self.datatype, self.extra)
+    }
+}
+
+/// A decoded column element, see [`FitsColumn::element`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Element<'a> {
+    /// Undefined: an integer equal to TNULL, a floating point NaN or a
+    /// logical that is neither true nor false
+    Null,
+    Bool(bool),
+    Integer(i128),
+    Float(f64),
+    String(&'a str),
+    Complex(f64, f64),
+}
+
+impl From<Element<'_>> for Value {
+    fn from(element: Element) -> Self {
+        match element {
+            Element::Null => Value::Null,
+            Element::Bool(b) => Value::Bool(b),
+            Element::Integer(n) => match i64::try_from(n) {
+                Ok(n) => n.into(),
+                Err(_) => (n as u64).into(),
+            },
+            Element::Float(x) => Number::from_f64(x).map_or(Value::Null, Value::Number),
+            Element::String(s) => Value::String(s.to_string()),
+            Element::Complex(real, imaginary) => json!({
+                "real": real,
+                "imaginary": imaginary,
+            }),
+        }
     }
 }
 
-fn parse_bytes(dtype: char, bytes: &[u8]) -> Result<Value> {
+/// The stored value of a single element of type `dtype`
+fn parse_bytes(dtype: char, bytes: &[u8]) -> Result<Element> {
     Ok(match dtype {
-        'L' => Value::Bool(bytes[0] != 0),
-        'X' => Value::Number(u8::from_be_bytes(bytes.try_into()?).into()),
-        'B' => Value::Number(u8::from_be_bytes(bytes.try_into()?).into()),
-        'I' => Value::Number(i16::from_be_bytes(bytes.try_into()?).into()),
-        'J' => Value::Number(i32::from_be_bytes(bytes.try_into()?).into()),
-        'K' => Value::Number(i64::from_be_bytes(bytes.try_into()?).into()),
-        'E' => Value::Number(
-            Number::from_f64(f32::from_be_bytes(bytes.try_into()?) as f64)
-                .ok_or(AstroError::new(&format!("Invalid f32: {:?}", bytes)))?,
+        'L' => match bytes[0] {
+            b'T' => Element::Bool(true),
+            b'F' => Element::Bool(false),
+            _ => Element::Null,
+        },
+        'X' | 'B' => Element::Integer(u8::from_be_bytes(bytes.try_into()?) as i128),
+        'I' => Element::Integer(i16::from_be_bytes(bytes.try_into()?) as i128),
+        'J' => Element::Integer(i32::from_be_bytes(bytes.try_into()?) as i128),
+        'K' => Element::Integer(i64::from_be_bytes(bytes.try_into()?) as i128),
+        'E' => Element::Float(f32::from_be_bytes(bytes.try_into()?) as f64),
+        'D' => Element::Float(f64::from_be_bytes(bytes.try_into()?)),
+        // Strings are padded with NULs or, more commonly, spaces
+        'A' => Element::String(std::str::from_utf8(bytes)?.trim_end_matches(['\0', ' '])),
+        'C' => Element::Complex(
+            f32::from_be_bytes(bytes[..4].try_into()?) as f64,
+            f32::from_be_bytes(bytes[4..].try_into()?) as f64,
         ),
-        'D' => Value::Number(
-            Number::from_f64(f64::from_be_bytes(bytes.try_into()?))
-                .ok_or(AstroError::new(&format!("Invalid f64: {:?}", bytes)))?,
+        'M' => Element::Complex(
+            f64::from_be_bytes(bytes[..8].try_into()?),
+            f64::from_be_bytes(bytes[8..].try_into()?),
         ),
-        'A' => Value::String(String::from_utf8(bytes.to_vec())?),
-        'C' => json!({
-            "real": f32::from_be_bytes(bytes[..4].try_into()?),
-            "imaginary": f32::from_be_bytes(bytes[4..].try_into()?),
-        }),
-        'M' => json!({
-            "real": f64::from_be_bytes(bytes[..8].try_into()?),
-            "imaginary": f64::from_be_bytes(bytes[8..].try_into()?),
-        }),
         _ => Err(AstroError::new(&format!(
             "Can't parse value: unknown column type: {}",
             dtype
@@ -67,6 +213,111 @@ fn parse_bytes(dtype: char, bytes: &[u8]) -> Result<Value> {
     })
 }
 
+/// Native types column elements can be decoded into, see [`FitsColumn::iter_as`]
+pub trait FromElement<'a>: Sized {
+    fn from_element(element: Element<'a>) -> Result<Self>;
+}
+
+fn conversion_error<T>(element: Element) -> anyhow::Error {
+    AstroError::new(&format!(
+        "Can't convert {:?} to {}",
+        element,
+        std::any::type_name::<T>()
+    ))
+    .into()
+}
+
+macro_rules! from_integer_element {
+    ($($t:ty),*) => {$(
+        impl FromElement<'_> for $t {
+            fn from_element(element: Element) -> Result<Self> {
+                match element {
+                    Element::Integer(n) => {
+                        Self::try_from(n).map_err(|_| conversion_error::<Self>(element))
+                    }
+                    _ => Err(conversion_error::<Self>(element)),
+                }
+            }
+        }
+    )*};
+}
+
+from_integer_element!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+/// Null elements are decoded as NaN
+impl FromElement<'_> for f64 {
+    fn from_element(element: Element) -> Result<Self> {
+        match element {
+            Element::Float(x) => Ok(x),
+            Element::Integer(n) => Ok(n as f64),
+            Element::Null => Ok(f64::NAN),
+            _ => Err(conversion_error::<Self>(element)),
+        }
+    }
+}
+
+/// Null elements are decoded as NaN
+impl FromElement<'_> for f32 {
+    fn from_element(element: Element) -> Result<Self> {
+        f64::from_element(element).map(|x| x as f32)
+    }
+}
+
+impl FromElement<'_> for bool {
+    fn from_element(element: Element) -> Result<Self> {
+        match element {
+            Element::Bool(b) => Ok(b),
+            _ => Err(conversion_error::<Self>(element)),
+        }
+    }
+}
+
+impl<'a> FromElement<'a> for &'a str {
+    fn from_element(element: Element<'a>) -> Result<Self> {
+        match element {
+            Element::String(s) => Ok(s),
+            _ => Err(conversion_error::<Self>(element)),
+        }
+    }
+}
+
+impl FromElement<'_> for String {
+    fn from_element(element: Element) -> Result<Self> {
+        <&str>::from_element(element).map(str::to_string)
+    }
+}
+
+impl<'a, T: FromElement<'a>> FromElement<'a> for Option<T> {
+    fn from_element(element: Element<'a>) -> Result<Self> {
+        match element {
+            Element::Null => Ok(None),
+            _ => T::from_element(element).map(Some),
+        }
+    }
+}
+
+fn read_number(hdu: &Hdu, key: &str) -> Result<Option<f64>> {
+    match hdu.value(key) {
+        None => Ok(None),
+        Some(HeaderValue::IntegerNumber(n)) => Ok(Some(*n as f64)),
+        Some(HeaderValue::RealFloatingNumber(x)) => Ok(Some(*x)),
+        Some(v) => Err(AstroError::new(&format!("{} Not a number: {:?}", key, v)))?,
+    }
+}
+
+fn read_integer(hdu: &Hdu, key: &str) -> Result<usize> {
+    match hdu
+        .value(key)
+        .ok_or(AstroError::new(&format!("Value not found: {}", key)))?
+    {
+        HeaderValue::IntegerNumber(n) if *n >= 0 => Ok(*n as usize),
+        v => Err(AstroError::new(&format!(
+            "{} Not a non-negative integer: {:?}",
+            key, v
+        )))?,
+    }
+}
+
 fn read_to_string(hdu: &Hdu, key: &str) -> Result<String> {
     match hdu
         .value(key)
@@ -109,9 +360,66 @@ impl<'a> Iterator for ColumnIter<'a> {
     }
 }
 
+/// A table's data mapped into memory, only the pages holding the rows and
+/// arrays that are accessed are ever read from disk.
 pub struct FitsTableData {
+    /// Row size and number of rows, from NAXIS1 and NAXIS2
     shape: Vec<usize>,
-    data: Vec<u8>,
+    mmap: Mmap,
+    table: Range<usize>,
+    /// Data of variable-length arrays, starting at THEAP
+    heap: Range<usize>,
+}
+
+impl FitsTableData {
+    /// Map the data of `hdu`, which starts at byte `start` of the file
+    fn map(path: &Path, start: u64, hdu: &Hdu) -> Result<Self> {
+        let shape = vec![read_integer(hdu, "NAXIS1")?, read_integer(hdu, "NAXIS2")?];
+        let pcount = read_integer(hdu, "PCOUNT")?;
+        let table_size = shape[0]
+            .checked_mul(shape[1])
+            .filter(|size| size.checked_add(pcount).is_some())
+            .ok_or(AstroError::new("NAXIS1, NAXIS2 and PCOUNT are too large"))?;
+
+        let theap = match hdu.value("THEAP") {
+            Some(_) => read_integer(hdu, "THEAP")?,
+            None => table_size,
+        };
+        ensure!(
+            (table_size..=table_size + pcount).contains(&theap),
+            AstroError::new(&format!(
+                "THEAP {} is outside the {} bytes after the table",
+                theap, pcount
+            ))
+        );
+
+        let start = start as usize;
+
+        // SAFETY: as for index files, we assume tables aren't modified while
+        // they are open. If they are, we may read garbage, but the slices we
+        // hand out never outlive the map.
+        let mmap = unsafe { Mmap::map(&File::open(path)?)? };
+
+        ensure!(
+            mmap.len() - start.min(mmap.len()) >= table_size + pcount,
+            AstroError::new("Table data is truncated")
+        );
+
+        Ok(Self {
+            shape,
+            mmap,
+            table: start..start + table_size,
+            heap: start + theap..start + table_size + pcount,
+        })
+    }
+
+    fn table(&self) -> &[u8] {
+        &self.mmap[self.table.clone()]
+    }
+
+    fn heap(&self) -> &[u8] {
+        &self.mmap[self.heap.clone()]
+    }
 }
 
 pub struct FitsColumn {
@@ -119,25 +427,115 @@ pub struct FitsColumn {
     name: String,
     format: String,
     unit: Option<String>,
-    datatype: char,
-    count: usize,
+    tform: TForm,
+    /// Physical values are `zero + scale * stored`, from TSCALn and TZEROn
+    scale: f64,
+    zero: f64,
+    /// Stored value of integer elements that are undefined, from TNULLn
+    null: Option<i64>,
     size: usize,
     offset: usize,
     data: Rc<FitsTableData>,
 }
 
 impl FitsColumn {
+    /// The physical value of a single element stored as `bytes`.
+    ///
+    /// Integers with TZERO set to the offset of the unsigned type of the same
+    /// size (or -128 for bytes, making them signed) and no scaling keep being
+    /// integers, other scaled numbers become floats.
+    pub fn element<'a>(&self, bytes: &'a [u8]) -> Result<Element<'a>> {
+        let dtype = self.element_type();
+
+        Ok(match parse_bytes(dtype, bytes)? {
+            Element::Integer(stored) if dtype != 'X' => {
+                if self.null.is_some_and(|null| null as i128 == stored) {
+                    Element::Null
+                } else if self.scale == 1.0 {
+                    match (dtype, self.zero) {
+                        ('B', -128.0) | ('I', 32768.0) | ('J', 2147483648.0) => {
+                            Element::Integer(stored + self.zero as i128)
+                        }
+                        ('K', 9223372036854775808.0) => Element::Integer(stored + (1 << 63)),
+                        (_, 0.0) => Element::Integer(stored),
+                        _ => Element::Float(self.zero + stored as f64),
+                    }
+                } else {
+                    Element::Float(self.zero + self.scale * stored as f64)
+                }
+            }
+            Element::Float(x) if x.is_nan() => Element::Null,
+            Element::Float(x) => Element::Float(self.zero + self.scale * x),
+            element => element,
+        })
+    }
+
+    /// Whether each row holds a single element, see [`TForm::is_scalar`]
+    pub fn is_scalar(&self) -> bool {
+        self.tform.is_scalar()
+    }
+
+    /// Type of the column's elements, which are in the heap for
+    /// variable-length arrays
+    fn element_type(&self) -> char {
+        self.tform.heap_datatype().unwrap_or(self.tform.datatype)
+    }
+
+    fn row_bytes(&self, row: usize) -> &[u8] {
+        &self.data.table()[row * self.data.shape[0]..][self.offset..self.offset + self.size]
+    }
+
+    /// The bytes of a row's elements, looked up in the heap for variable-length
+    /// arrays
+    fn cell(&self, row: usize) -> Result<&[u8]> {
+        let bytes = self.row_bytes(row);
+        let (count, offset) = match self.tform.datatype {
+            'P' => (
+                u32::from_be_bytes(bytes[..4].try_into()?) as u64,
+                u32::from_be_bytes(bytes[4..].try_into()?) as u64,
+            ),
+            'Q' => (
+                u64::from_be_bytes(bytes[..8].try_into()?),
+                u64::from_be_bytes(bytes[8..].try_into()?),
+            ),
+            _ => return Ok(bytes),
+        };
+
+        let size = match self.element_type() {
+            'X' => count.div_ceil(8),
+            dtype => count.saturating_mul(datatype_size(dtype)? as u64),
+        };
+
+        offset
+            .checked_add(size)
+            .and_then(|end| self.data.heap().get(offset as usize..end as usize))
+            .ok_or(
+                AstroError::new(&format!(
+                    "Array of {} elements at {} in row {} of column {} is outside the heap",
+                    count, offset, row, self.name
+                ))
+                .into(),
+            )
+    }
+
+    /// The bytes of each element of a row
+    fn split<'a>(&self, bytes: &'a [u8]) -> Either<std::iter::Once<&'a [u8]>, Chunks<'a, u8>> {
+        if self.is_scalar() {
+            Either::Left(std::iter::once(bytes))
+        } else {
+            Either::Right(bytes.chunks(datatype_size(self.element_type()).unwrap()))
+        }
+    }
+
     pub fn value(&self, row: usize) -> Result<Value> {
-        let bytes =
-            &self.data.data[row * self.data.shape[0]..][self.offset..self.offset + self.size];
+        let bytes = self.cell(row)?;
 
-        if self.count == 1 || self.datatype == 'X' {
-            parse_bytes(self.datatype, bytes)
+        if self.is_scalar() {
+            Ok(self.element(bytes)?.into())
         } else {
             let array = Value::Array(
-                bytes
-                    .chunks(datatype_size(&self.format)?)
-                    .map(|chunk| parse_bytes(self.datatype, chunk))
+                self.split(bytes)
+                    .map(|chunk| Ok(self.element(chunk)?.into()))
                     .collect::<Result<Vec<_>>>()?,
             );
 
@@ -145,9 +543,34 @@ impl FitsColumn {
         }
     }
 
+    /// Decode the column's elements into `T` without going through JSON,
+    /// row by row. Rows with several elements yield all of them in order.
+    pub fn iter_as<'a, T: FromElement<'a>>(&'a self) -> impl Iterator<Item = Result<T>> + 'a {
+        (0..self.data.shape[1])
+            .map(|row| self.cell(row))
+            .flat_map(|bytes| match bytes {
+                Ok(bytes) => Either::Left(self.split(bytes).map(Ok)),
+                Err(e) => Either::Right(std::iter::once(Err(e))),
+            })
+            .map(|bytes| T::from_element(self.element(bytes?)?))
+    }
+
+    /// All elements of the column, see [`Self::iter_as`].
+    pub fn to_vec<'a, T: FromElement<'a>>(&'a self) -> Result<Vec<T>> {
+        self.iter_as().collect()
+    }
+
+    pub fn iter_f32(&self) -> impl Iterator<Item = Result<f32>> + '_ {
+        self.iter_as()
+    }
+
+    pub fn iter_f64(&self) -> impl Iterator<Item = Result<f64>> + '_ {
+        self.iter_as()
+    }
+
     pub fn iter(&self) -> ColumnIter {
         ColumnIter::new(
-            &self.data.data,
+            self.data.table(),
             self.data.shape[0],
             [self.offset, self.size],
         )
@@ -168,6 +591,22 @@ impl FitsColumn {
     pub fn unit(&self) -> Option<&str> {
         self.unit.as_deref()
     }
+
+    pub fn tform(&self) -> &TForm {
+        &self.tform
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub fn zero(&self) -> f64 {
+        self.zero
+    }
+
+    pub fn null(&self) -> Option<i64> {
+        self.null
+    }
 }
 
 pub struct FitsTable {
@@ -177,17 +616,55 @@ pub struct FitsTable {
 }
 
 impl FitsTable {
-    pub fn open(path: &str, hdu_index: usize) -> Result<Self> {
+    /// Open the binary table in HDU `hdu_index` of a file. Its data is mapped
+    /// rather than read, so tables larger than memory can be iterated over.
+    ///
+    /// Errors name the file, the HDU and the header keyword at fault.
+    pub fn open(path: impl AsRef<Path>, hdu_index: usize) -> Result<Self> {
+        let path = path.as_ref();
+
+        Self::open_hdu(path, hdu_index).map_err(|e| {
+            AstroError::new(&format!("{}, HDU {}: {}", path.display(), hdu_index, e)).into()
+        })
+    }
+
+    /// Open the binary table whose EXTNAME is `name`, see [`HduInfo::matches`].
+    /// Without `version`, the first HDU of that name is opened.
+    pub fn open_extension(
+        path: impl AsRef<Path>,
+        name: &str,
+        version: Option<i64>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let hdu = list_hdus(path)?
+            .into_iter()
+            .find(|hdu| hdu.matches(name, version))
+            .ok_or(AstroError::new(&format!(
+                "{}: no HDU named {}{}",
+                path.display(),
+                name,
+                version.map_or(String::new(), |v| format!(" with EXTVER {}", v))
+            )))?;
+
+        Self::open(path, hdu.index)
+    }
+
+    fn open_hdu(path: &Path, hdu_index: usize) -> Result<Self> {
+        // Walking the headers ourselves first rejects dimensions fitrs would
+        // trust, such as a negative NAXIS
+        let start = hdus::data_offset(path, hdu_index)?;
+
         let hdu = Fits::open(path)?
             .get(hdu_index)
             .ok_or(AstroError::new("HDU does not exist"))?;
 
-        let data = match hdu.read_data() {
-            FitsData::Bytes(FitsDataArray { shape, data }) => FitsTableData { shape, data },
-            _ => Err(AstroError::new("Table data not in bytes"))?,
-        };
+        let xtension = read_to_string(&hdu, "XTENSION").unwrap_or_default();
+        ensure!(
+            xtension.trim_end() == "BINTABLE",
+            AstroError::new(&format!("Not a binary table: XTENSION is {:?}", xtension))
+        );
 
-        let data = Rc::new(data);
+        let data = Rc::new(FitsTableData::map(path, start, &hdu)?);
 
         let table = FitsTable {
             data: Rc::clone(&data),
@@ -199,23 +676,23 @@ impl FitsTable {
     }
 
     fn get_columns(hdu: &Hdu, data: Rc<FitsTableData>) -> Result<HashMap<String, FitsColumn>> {
-        let nfields = match hdu.value("TFIELDS").unwrap() {
-            HeaderValue::IntegerNumber(n) => *n,
-            v => panic!("TFIELDS Not an integer: {:?}", v),
-        };
+        let nfields = read_integer(hdu, "TFIELDS")?;
 
         let mut columns = HashMap::new();
-        let mut offset = 0;
+        let mut offset = 0usize;
 
         for i in 1..=nfields {
             let format = read_to_string(hdu, &format!("TFORM{}", i))?;
             let name = read_to_string(hdu, &format!("TTYPE{}", i))?;
             let unit = read_to_string(hdu, &format!("TUNIT{}", i)).ok();
 
-            let count = format[..1].parse::<usize>().unwrap();
-            let datatype = format.chars().nth(1).unwrap();
+            let tform = TForm::parse(&format)
+                .map_err(|e| AstroError::new(&format!("TFORM{}: {}", i, e)))?;
+            let column_size = tform.width();
 
-            let column_size = datatype_size(&format)?;
+            let scale = read_number(hdu, &format!("TSCAL{}", i))?.unwrap_or(1.0);
+            let zero = read_number(hdu, &format!("TZERO{}", i))?.unwrap_or(0.0);
+            let null = read_number(hdu, &format!("TNULL{}", i))?.map(|n| n as i64);
 
             columns.insert(
                 name.clone(),
@@ -223,8 +700,10 @@ impl FitsTable {
                     index: columns.len(),
                     name,
                     format,
-                    datatype,
-                    count,
+                    tform,
+                    scale,
+                    zero,
+                    null,
                     unit,
                     size: column_size,
                     data: Rc::clone(&data),
@@ -232,23 +711,29 @@ impl FitsTable {
                 },
             );
 
-            offset += column_size;
+            offset = offset
+                .checked_add(column_size)
+                .ok_or(AstroError::new(&format!("TFORM{}: column too wide", i)))?;
         }
 
+        ensure!(
+            offset == data.shape[0],
+            AstroError::new(&format!(
+                "Columns take up {} bytes, but NAXIS1 is {}",
+                offset, data.shape[0]
+            ))
+        );
+
         Ok(columns)
     }
 
-    pub fn deserialize_row<T: DeserializeOwned>(&self, row: usize) -> Result<T> {
-        let mut values = Map::new();
-
-        for (column_name, column) in self.columns.iter() {
-            values.insert(column_name.clone(), column.value(row)?);
-        }
-
-        Ok(serde_json::from_value(Value::Object(values))?)
+    /// Deserialize a row as a map from column names to values, see
+    /// [`RowDeserializer`]. Strings can be borrowed from the table.
+    pub fn deserialize_row<'a, T: Deserialize<'a>>(&'a self, row: usize) -> Result<T> {
+        Ok(T::deserialize(RowDeserializer::new(self, row))?)
     }
 
-    pub fn iter<T: DeserializeOwned>(&self) -> impl Iterator<Item = Result<T>> + '_ {
+    pub fn iter<'a, T: Deserialize<'a>>(&'a self) -> impl Iterator<Item = Result<T>> + 'a {
         (0..self.data.shape[1]).map(|row| self.deserialize_row(row))
     }
 
@@ -268,3 +753,565 @@ impl FitsTable {
         self.len() == 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::util::{from_crate_root, temp_path};
+
+    use super::*;
+
+    fn open(name: &str, hdu: usize) -> FitsTable {
+        FitsTable::open(from_crate_root(name), hdu).unwrap()
+    }
+
+    #[test]
+    fn test_tform() {
+        let tform = |format: &str| TForm::parse(format).unwrap();
+
+        assert_eq!(tform("E").repeat, 1);
+        assert_eq!(tform("1E").repeat, 1);
+        assert_eq!((tform("12E").repeat, tform("12E").width()), (12, 48));
+        assert_eq!((tform("120D").repeat, tform("120D").width()), (120, 960));
+        assert_eq!(tform("0J").width(), 0);
+        assert_eq!(tform("17X").width(), 3);
+        assert_eq!(tform("8X").width(), 1);
+        assert_eq!(tform(" 4J ").repeat, 4);
+        assert_eq!(tform("20A:SSTR8").extra, ":SSTR8");
+        assert_eq!(tform("20A:SSTR8").width(), 20);
+
+        for invalid in ["", "12", "Z", "3Y", "-1E"] {
+            assert!(TForm::parse(invalid).is_err());
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Source {
+        #[serde(rename = "X")]
+        x: f32,
+        #[serde(rename = "Y")]
+        y: f32,
+        #[serde(rename = "FLUX")]
+        flux: f32,
+        #[serde(rename = "BACKGROUND")]
+        background: f32,
+    }
+
+    #[test]
+    fn test_xylist() {
+        // Source lists as written by image2xy have implicit repeat counts
+        let table = open("testdata/xylist.fits", 1);
+
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.columns()["X"].format(), "E");
+        assert_eq!(table.columns()["X"].unit(), Some("pix"));
+
+        let sources = table.iter::<Source>().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(
+            sources[0],
+            Source {
+                x: 512.25,
+                y: 384.5,
+                flux: 1520.0,
+                background: 101.5
+            }
+        );
+        assert_eq!((sources[2].x, sources[2].y), (1000.5, 700.75));
+    }
+
+    #[test]
+    fn test_index_tables() {
+        let quads = open("testdata/index.fits", 1);
+        assert_eq!(quads.len(), 2);
+        assert_eq!(
+            quads.columns()["quads"].value(1).unwrap(),
+            json!([1, 2, 3, 4])
+        );
+
+        let stars = open("testdata/index.fits", 2);
+        assert_eq!(stars.len(), 3);
+
+        let columns = stars.columns();
+        assert_eq!(columns["ra"].value(2).unwrap(), json!(250.75));
+        assert_eq!(columns["mag"].value(0).unwrap(), json!(12.25));
+        assert_eq!(columns["id"].value(1).unwrap(), json!("A0000-002"));
+        assert_eq!(
+            columns["flux"].value(1).unwrap(),
+            json!([0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0])
+        );
+        assert_eq!(columns["flags"].tform().width(), 2);
+        assert_eq!(columns["flags"].value(0).unwrap(), json!([0, 192]));
+    }
+
+    #[test]
+    fn test_astrometry_net_xylist() {
+        // Headers as astrometry.net writes them: free-format values, explicit
+        // repeat counts and empty units, followed by HISTORY, COMMENT and
+        // CONTINUE cards the table doesn't depend on
+        let table = open("testdata/an-xylist.fits", 1);
+
+        assert_eq!((table.len(), table.columns().len()), (5, 4));
+        assert_eq!(
+            table.hdu().value("IMAGEW"),
+            Some(&HeaderValue::IntegerNumber(1600))
+        );
+        let x = &table.columns()["X"];
+        assert_eq!(x.format(), "1E");
+        assert!(x.is_scalar());
+        assert_eq!(x.tform().width(), 4);
+        assert!(x.unit().is_none_or(str::is_empty));
+
+        let sources = table.iter::<Source>().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!((sources[0].x, sources[0].y), (1021.3853, 12.617906));
+        assert_eq!(sources[4].flux, 9876.376);
+        assert_eq!(sources[4].background, 1199.8906);
+    }
+
+    #[test]
+    fn test_astrometry_net_index() {
+        // Index chunks are single character columns holding native-endian
+        // binary data, only readable as raw bytes
+        let quads = open("testdata/an-index.fits", 1);
+        let column = &quads.columns()["quads"];
+        assert_eq!(column.format(), "16A");
+        assert_eq!(column.tform().width(), 16);
+
+        let quads = column
+            .iter()
+            .map(|bytes| {
+                bytes
+                    .chunks_exact(4)
+                    .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(quads, [[0, 3, 7, 12], [3, 7, 12, 20], [1, 4, 9, 15]]);
+
+        let stars = open("testdata/an-index.fits", 2);
+        let column = &stars.columns()["kdtree_data_stars"];
+        assert_eq!((stars.len(), column.tform().width()), (3, 12));
+        assert_eq!(
+            column.iter().next().unwrap(),
+            [0, 0, 0, 128, 0, 0, 192, 0, 255, 255, 255, 255]
+        );
+        assert!(column.value(0).is_err());
+    }
+
+    #[test]
+    fn test_padded_strings() {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "UPPERCASE")]
+        struct Row<'a> {
+            name: &'a str,
+            row: i32,
+        }
+
+        let table = open("testdata/strings.fits", 1);
+        let column = &table.columns()["NAME"];
+        let expected = ["abc", "  lead", "", "two word", "nul"];
+
+        assert_eq!(column.value(0).unwrap(), json!("abc"));
+        assert_eq!(column.to_vec::<&str>().unwrap(), expected);
+
+        let rows = table.iter::<Row>().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(rows.iter().map(|r| r.name).collect::<Vec<_>>(), expected);
+        assert_eq!(rows[4].row, 5);
+    }
+
+    #[test]
+    fn test_scaling() {
+        let table = open("testdata/scaled.fits", 1);
+        let value = |column: &str, row: usize| table.columns()[column].value(row).unwrap();
+
+        assert_eq!(value("U16", 0), json!(65535u16));
+        assert_eq!(value("U16", 1), json!(0));
+        assert_eq!(value("U32", 0), json!(u32::MAX));
+        assert_eq!(value("U32", 1), json!(0));
+        assert_eq!(value("U64", 0), json!(u64::MAX));
+        assert_eq!(value("U64", 1), json!(0));
+        assert_eq!(value("S8", 0), json!(127));
+        assert_eq!(value("S8", 1), json!(-128));
+        assert_eq!(value("SCALED", 0), json!(105.0));
+        assert_eq!(value("SCALED", 1), json!(98.0));
+        assert_eq!(value("NULLED", 0), Value::Null);
+        assert_eq!(value("NULLED", 1), json!(7));
+        assert_eq!(value("FLOAT", 0), Value::Null);
+        assert_eq!(value("FLOAT", 1), json!(1.5));
+        assert_eq!(value("U16S", 0), json!([0, 32768, 65535]));
+
+        let column = &table.columns()["SCALED"];
+        assert_eq!(
+            (column.scale(), column.zero(), column.null()),
+            (0.5, 100.0, None)
+        );
+        assert_eq!(table.columns()["NULLED"].null(), Some(-1));
+
+        #[derive(Deserialize)]
+        struct Row {
+            #[serde(rename = "U64")]
+            u64: u64,
+            #[serde(rename = "NULLED")]
+            nulled: Option<i32>,
+            #[serde(rename = "FLOAT")]
+            float: Option<f32>,
+        }
+
+        let rows = table.iter::<Row>().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(rows[0].u64, u64::MAX);
+        assert_eq!((rows[0].nulled, rows[0].float), (None, None));
+        assert_eq!((rows[1].nulled, rows[1].float), (Some(7), Some(1.5)));
+    }
+
+    #[test]
+    fn test_typed_columns() {
+        let xylist = open("testdata/xylist.fits", 1);
+        let x = &xylist.columns()["X"];
+        assert_eq!(x.to_vec::<f32>().unwrap()[2], 1000.5);
+        assert_eq!(x.iter_f64().collect::<Result<Vec<_>>>().unwrap()[0], 512.25);
+        assert!(x.to_vec::<i32>().is_err());
+
+        let stars = open("testdata/index.fits", 2);
+        let ids = stars.columns()["id"].to_vec::<&str>().unwrap();
+        assert_eq!(ids[1], "A0000-002");
+
+        #[derive(Deserialize)]
+        struct Star<'a> {
+            id: &'a str,
+            flux: Vec<f32>,
+        }
+
+        let star: Star = stars.deserialize_row(1).unwrap();
+        assert_eq!(star.id, "A0000-002");
+        assert_eq!(star.flux.len(), 10);
+        assert_eq!(star.flux[9], 9.0);
+
+        let scaled = open("testdata/scaled.fits", 1);
+        let columns = scaled.columns();
+        assert_eq!(
+            columns["NULLED"].to_vec::<Option<i32>>().unwrap(),
+            [None, Some(7)]
+        );
+        assert_eq!(columns["U64"].to_vec::<u64>().unwrap(), [u64::MAX, 0]);
+        assert_eq!(columns["S8"].to_vec::<i8>().unwrap(), [127, -128]);
+        assert!(columns["U64"].to_vec::<i8>().is_err());
+        assert!(columns["NULLED"].to_vec::<i32>().is_err());
+        assert!(columns["FLOAT"].to_vec::<f32>().unwrap()[0].is_nan());
+    }
+
+    #[test]
+    fn test_variable_length_arrays() {
+        let table = open("testdata/vla.fits", 1);
+        let columns = table.columns();
+        let value = |column: &str, row: usize| columns[column].value(row).unwrap();
+
+        assert_eq!(table.len(), 3);
+        assert_eq!(columns["FLUX"].tform().heap_datatype(), Some('E'));
+        assert_eq!(value("FLUX", 0), json!([1.5, 2.5]));
+        assert_eq!(value("FLUX", 1), json!([]));
+        assert_eq!(value("IDS", 0), json!([10, 20, 30]));
+        assert_eq!(value("NAME", 2), json!("third row"));
+        assert_eq!(value("NAME", 1), json!(""));
+        assert_eq!(value("U16", 0), json!([65535]));
+        assert_eq!(value("U16", 2), json!([0, 32768]));
+
+        assert_eq!(
+            columns["FLUX"].to_vec::<f32>().unwrap(),
+            [1.5, 2.5, 4.0, 5.0, 6.0, 7.0]
+        );
+        assert_eq!(
+            columns["NAME"].to_vec::<&str>().unwrap(),
+            ["first", "", "third row"]
+        );
+
+        #[derive(Deserialize)]
+        struct Row<'a> {
+            #[serde(rename = "ROW")]
+            row: i32,
+            #[serde(rename = "IDS")]
+            ids: Vec<u64>,
+            #[serde(rename = "NAME")]
+            name: &'a str,
+        }
+
+        let rows = table.iter::<Row>().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(rows[2].row, 3);
+        assert_eq!(rows[2].ids, [40]);
+        assert_eq!(rows[0].name, "first");
+
+        // The heap is skipped when looking for the next HDU
+        assert_eq!(open("testdata/vla.fits", 2).len(), 3);
+
+        for invalid in ["2PE(4)", "PQ(4)", "PZ", "P"] {
+            assert!(TForm::parse(invalid).is_err());
+        }
+        assert!(ColumnDef::new("FLUX", "PE(4)").is_err());
+    }
+
+    #[test]
+    fn test_mapped_table() {
+        let path = temp_path("fits-mapped.fits");
+        let rows = 100_000;
+
+        let mut writer = FitsTableWriter::create(&path).unwrap();
+        writer
+            .write_columns(
+                "ROWS",
+                &[
+                    ColumnData::new(ColumnDef::new("ROW", "J").unwrap(), 0..rows).unwrap(),
+                    ColumnData::new(
+                        ColumnDef::new("HALF", "D").unwrap(),
+                        (0..rows).map(|row| row as f64 / 2.0),
+                    )
+                    .unwrap(),
+                ],
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        #[derive(Deserialize)]
+        struct Row {
+            #[serde(rename = "ROW")]
+            row: i32,
+            #[serde(rename = "HALF")]
+            half: f64,
+        }
+
+        let table = FitsTable::open(&path, 1).unwrap();
+        assert_eq!(table.len(), rows as usize);
+        for (i, row) in table.iter::<Row>().enumerate() {
+            let row = row.unwrap();
+            assert_eq!((row.row, row.half), (i as i32, i as f64 / 2.0));
+        }
+
+        // Rows missing from the end of the file
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(2 * 2880 + 1000).unwrap();
+        assert!(FitsTable::open(&path, 1).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// A one-column table whose header has `changes` applied, None removing
+    /// a keyword
+    fn malformed_table(name: &str, changes: &[(&str, Option<&str>)]) -> String {
+        let mut cards = vec![
+            ("XTENSION", "'BINTABLE'"),
+            ("BITPIX", "8"),
+            ("NAXIS", "2"),
+            ("NAXIS1", "4"),
+            ("NAXIS2", "1"),
+            ("PCOUNT", "0"),
+            ("GCOUNT", "1"),
+            ("TFIELDS", "1"),
+            ("TTYPE1", "'ROW'"),
+            ("TFORM1", "'J'"),
+        ];
+        for (key, value) in changes {
+            cards.retain(|(k, _)| k != key);
+            if let Some(value) = value {
+                cards.push((key, value));
+            }
+        }
+
+        let card = |key: &str, value: &str| format!("{:<80}", format!("{:<8}= {:>20}", key, value));
+
+        let mut header = [card("SIMPLE", "T"), card("BITPIX", "8"), card("NAXIS", "0")].concat();
+        header.push_str(&format!("{:<2640}", "END"));
+        for (key, value) in cards {
+            header.push_str(&card(key, value));
+        }
+        header.push_str(&format!("{:<80}", "END"));
+        header.push_str(&" ".repeat(2 * 2880 - header.len()));
+
+        let path = temp_path(&format!("{}.fits", name));
+        let mut bytes = header.into_bytes();
+        bytes.resize(3 * 2880, 0);
+        fs::write(&path, bytes).unwrap();
+
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_malformed_headers() {
+        let check = |name: &str, changes: &[(&str, Option<&str>)], keyword: &str| {
+            let path = malformed_table(name, changes);
+            let error = FitsTable::open(&path, 1).err().unwrap().to_string();
+
+            assert!(error.contains(&path), "{}", error);
+            assert!(error.contains("HDU 1"), "{}", error);
+            assert!(error.contains(keyword), "{}", error);
+
+            fs::remove_file(&path).unwrap();
+        };
+
+        check("no-tfields", &[("TFIELDS", None)], "TFIELDS");
+        check("string-tfields", &[("TFIELDS", Some("'one'"))], "TFIELDS");
+        check("bad-tform", &[("TFORM1", Some("'Z'"))], "TFORM1");
+        check(
+            "huge-tform",
+            &[("TFORM1", Some("'4611686018427387904E'"))],
+            "TFORM1",
+        );
+        check("no-ttype", &[("TTYPE1", None)], "TTYPE1");
+        check("wide-rows", &[("NAXIS1", Some("8"))], "NAXIS1");
+        check("no-naxis2", &[("NAXIS2", None)], "NAXIS2");
+        check("bad-theap", &[("THEAP", Some("2"))], "THEAP");
+        check("many-axes", &[("NAXIS", Some("1000"))], "NAXIS");
+        check("negative-axes", &[("NAXIS", Some("-1"))], "NAXIS");
+        // 3 * 2^62 bytes fit in a u64 but can't be seeked past
+        check(
+            "huge-data",
+            &[("NAXIS2", Some("3458764513820540928"))],
+            "data size",
+        );
+
+        let path = malformed_table("valid", &[]);
+        assert_eq!(FitsTable::open(&path, 1).unwrap().len(), 1);
+        assert!(FitsTable::open(&path, 0).is_err());
+        assert!(FitsTable::open(&path, 2)
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("HDU 2"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_hdus() {
+        let hdus = list_hdus(from_crate_root("testdata/vla.fits")).unwrap();
+        assert_eq!(
+            hdus.iter()
+                .map(|hdu| (hdu.hdu_type.clone(), hdu.dimensions.clone()))
+                .collect::<Vec<_>>(),
+            [
+                (HduType::Primary, vec![]),
+                (HduType::BinTable, vec![44, 3]),
+                (HduType::BinTable, vec![16, 3]),
+            ]
+        );
+        assert_eq!(hdus[1].name.as_deref(), Some("VLA"));
+        assert_eq!(hdus[2].name, None);
+
+        let path = from_crate_root("testdata/index.fits");
+        let hdus = list_hdus(&path).unwrap();
+        assert_eq!(
+            hdus.iter()
+                .map(|hdu| hdu.name.as_deref())
+                .collect::<Vec<_>>(),
+            [None, Some("quads"), Some("stars")]
+        );
+
+        let stars = FitsTable::open_extension(&path, "stars", None).unwrap();
+        assert_eq!(stars.len(), 3);
+        assert!(stars.columns().contains_key("flux"));
+        assert_eq!(
+            FitsTable::open_extension(&path, "QUADS", Some(1))
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(FitsTable::open_extension(&path, "quads", Some(2)).is_err());
+        assert!(FitsTable::open_extension(&path, "sources", None).is_err());
+    }
+
+    #[test]
+    fn test_write_tables() {
+        let path = temp_path("fits-writer.fits");
+
+        let sources = [
+            Source {
+                x: 512.25,
+                y: 384.5,
+                flux: 1520.0,
+                background: 101.5,
+            },
+            Source {
+                x: 10.0,
+                y: 20.0,
+                flux: 30.0,
+                background: 40.0,
+            },
+        ];
+        let source_columns = ["X", "Y", "FLUX", "BACKGROUND"]
+            .map(|name| ColumnDef::new(name, "E").unwrap().with_unit("pix"));
+
+        let column = |name: &str, format: &str| ColumnDef::new(name, format).unwrap();
+        let stars = [
+            ColumnData::new(column("ID", "9A"), ["A0000-001", "A0000-002"]).unwrap(),
+            ColumnData::new(column("U16", "I").with_scaling(1.0, 32768.0), [65535u16, 0]).unwrap(),
+            ColumnData::new(
+                column("U64", "K").with_scaling(1.0, 9223372036854775808.0),
+                [u64::MAX, 0],
+            )
+            .unwrap(),
+            ColumnData::new(
+                column("MAG", "E").with_scaling(0.5, 10.0),
+                [Some(11.0), None],
+            )
+            .unwrap(),
+            ColumnData::new(column("NULLED", "J").with_null(-1), [None, Some(7)]).unwrap(),
+            ColumnData::new(column("FLUX", "3D"), [[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]).unwrap(),
+            ColumnData::new(column("FLAGS", "10X"), [[0u8, 192], [255, 0]]).unwrap(),
+            ColumnData::new(column("GOOD", "L"), [true, false]).unwrap(),
+        ];
+
+        let mut writer = FitsTableWriter::create(&path).unwrap();
+        writer
+            .write_rows("SOURCES", &source_columns, &sources)
+            .unwrap();
+        writer.write_columns("STARS", &stars).unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().len() % 2880, 0);
+
+        let table = FitsTable::open(&path, 1).unwrap();
+        assert_eq!(table.columns()["X"].unit(), Some("pix"));
+        assert_eq!(
+            table.iter::<Source>().collect::<Result<Vec<_>>>().unwrap(),
+            sources
+        );
+
+        let table = FitsTable::open(&path, 2).unwrap();
+        let value = |column: &str, row: usize| table.columns()[column].value(row).unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(value("ID", 1), json!("A0000-002"));
+        assert_eq!(value("U16", 0), json!(65535));
+        assert_eq!(value("U64", 0), json!(u64::MAX));
+        assert_eq!(value("U64", 1), json!(0));
+        assert_eq!(value("MAG", 0), json!(11.0));
+        assert_eq!(value("MAG", 1), Value::Null);
+        assert_eq!(value("NULLED", 0), Value::Null);
+        assert_eq!(value("NULLED", 1), json!(7));
+        assert_eq!(value("FLUX", 1), json!([4.0, 5.0, 6.0]));
+        assert_eq!(value("FLAGS", 0), json!([0, 192]));
+        assert_eq!(value("GOOD", 1), json!(false));
+        assert_eq!(table.columns()["FLAGS"].format(), "10X");
+
+        fs::remove_file(&path).unwrap();
+
+        assert!(ColumnData::new(column("C", "4A"), ["too long"]).is_err());
+        assert!(ColumnData::new(column("C", "3D"), [[1.0, 2.0]]).is_err());
+        assert!(ColumnData::new(column("C", "D"), [[1.0, 2.0]]).is_err());
+        assert!(ColumnData::new(column("C", "2J"), [1]).is_err());
+        assert!(ColumnData::new(column("C", "B"), [256]).is_err());
+        assert!(ColumnData::new(column("C", "J"), [None::<i32>]).is_err());
+        assert!(ColumnData::new(column("C", "J"), ["text"]).is_err());
+
+        let mut writer = FitsTableWriter::new(Vec::new()).unwrap();
+        assert!(writer
+            .write_rows("SOURCES", &source_columns[..2], &sources)
+            .is_err());
+        assert!(writer
+            .write_columns(
+                "MISMATCHED",
+                &[
+                    ColumnData::new(column("A", "J"), [1, 2]).unwrap(),
+                    ColumnData::new(column("B", "J"), [1]).unwrap(),
+                ],
+            )
+            .is_err());
+    }
+}
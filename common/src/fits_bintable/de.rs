@@ -0,0 +1,209 @@
+/// Deserializing table rows straight from their bytes
+use std::fmt::Display;
+
+use serde::{
+    de::{
+        self,
+        value::{MapDeserializer, SeqDeserializer},
+        DeserializeSeed, IntoDeserializer, MapAccess, Visitor,
+    },
+    forward_to_deserialize_any,
+};
+
+use crate::error::AstroError;
+
+use super::{Element, FitsColumn, FitsTable};
+
+impl de::Error for AstroError {
+    fn custom<T: Display>(message: T) -> Self {
+        AstroError::new(&message.to_string())
+    }
+}
+
+/// Deserializes a row of a table as a map from column names to values,
+/// decoding each value as it is visited. Values are what
+/// [`FitsColumn::value`] would return, without building them as JSON first.
+pub struct RowDeserializer<'a> {
+    table: &'a FitsTable,
+    row: usize,
+}
+
+impl<'a> RowDeserializer<'a> {
+    pub fn new(table: &'a FitsTable, row: usize) -> Self {
+        Self { table, row }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for RowDeserializer<'de> {
+    type Error = AstroError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, AstroError> {
+        visitor.visit_map(RowAccess {
+            columns: self
+                .table
+                .columns()
+                .values()
+                .collect::<Vec<_>>()
+                .into_iter(),
+            row: self.row,
+            column: None,
+        })
+    }
+
+    /// Only decodes the columns the struct has fields for
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, AstroError> {
+        let columns = self.table.columns();
+
+        visitor.visit_map(RowAccess {
+            columns: fields
+                .iter()
+                .filter_map(|field| columns.get(*field))
+                .collect::<Vec<_>>()
+                .into_iter(),
+            row: self.row,
+            column: None,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+struct RowAccess<'a> {
+    columns: std::vec::IntoIter<&'a FitsColumn>,
+    row: usize,
+    column: Option<&'a FitsColumn>,
+}
+
+impl<'de> MapAccess<'de> for RowAccess<'de> {
+    type Error = AstroError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, AstroError> {
+        self.column = self.columns.next();
+
+        self.column
+            .map(|column| seed.deserialize(column.name().into_deserializer()))
+            .transpose()
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, AstroError> {
+        let column = self
+            .column
+            .take()
+            .ok_or(AstroError::new("Value requested before key"))?;
+
+        seed.deserialize(ColumnDeserializer {
+            column,
+            bytes: column
+                .cell(self.row)
+                .map_err(|e| AstroError::new(&e.to_string()))?,
+        })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.columns.len())
+    }
+}
+
+/// A column's value in one row: a single element or a sequence of them
+struct ColumnDeserializer<'a> {
+    column: &'a FitsColumn,
+    bytes: &'a [u8],
+}
+
+impl<'de> ColumnDeserializer<'de> {
+    fn element(&self) -> Result<Element<'de>, AstroError> {
+        self.column
+            .element(self.bytes)
+            .map_err(|e| AstroError::new(&e.to_string()))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ColumnDeserializer<'de> {
+    type Error = AstroError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, AstroError> {
+        if self.column.is_scalar() {
+            return self.element()?.deserialize_any(visitor);
+        }
+
+        let elements = self
+            .column
+            .split(self.bytes)
+            .map(|bytes| self.column.element(bytes))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|e| AstroError::new(&e.to_string()))?;
+
+        visitor.visit_seq(SeqDeserializer::new(elements.into_iter()))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, AstroError> {
+        if self.column.is_scalar() && self.element()? == Element::Null {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Element<'de> {
+    type Error = AstroError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, AstroError> {
+        match self {
+            Element::Null => visitor.visit_unit(),
+            Element::Bool(b) => visitor.visit_bool(b),
+            Element::Integer(n) => match (i64::try_from(n), u64::try_from(n)) {
+                (Ok(n), _) => visitor.visit_i64(n),
+                (_, Ok(n)) => visitor.visit_u64(n),
+                _ => visitor.visit_i128(n),
+            },
+            Element::Float(x) => visitor.visit_f64(x),
+            Element::String(s) => visitor.visit_borrowed_str(s),
+            Element::Complex(real, imaginary) => visitor.visit_map(MapDeserializer::new(
+                [("real", real), ("imaginary", imaginary)].into_iter(),
+            )),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, AstroError> {
+        match self {
+            Element::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, AstroError> for Element<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
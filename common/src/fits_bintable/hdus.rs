@@ -0,0 +1,184 @@
+/// Walking the headers of the HDUs in a file
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, ErrorKind, Read, Seek},
+    path::Path,
+};
+
+use anyhow::{ensure, Result};
+
+use crate::error::AstroError;
+
+use super::{BLOCK_SIZE, CARD_SIZE};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HduType {
+    Primary,
+    Image,
+    BinTable,
+    AsciiTable,
+    /// An extension of another type, given by its XTENSION
+    Other(String),
+}
+
+/// What an HDU's header says about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct HduInfo {
+    pub index: usize,
+    pub hdu_type: HduType,
+    /// NAXIS1, NAXIS2, ... For binary tables, the row size and number of rows
+    pub dimensions: Vec<usize>,
+    /// EXTNAME and EXTVER
+    pub name: Option<String>,
+    pub version: Option<i64>,
+    /// Offset of the HDU's data in the file
+    data_offset: u64,
+}
+
+impl HduInfo {
+    /// Whether the HDU is named `name`, ignoring case, and has version
+    /// `version` if given. HDUs without EXTVER are version 1.
+    pub fn matches(&self, name: &str, version: Option<i64>) -> bool {
+        self.name
+            .as_ref()
+            .is_some_and(|n| n.eq_ignore_ascii_case(name))
+            && version.is_none_or(|v| self.version.unwrap_or(1) == v)
+    }
+}
+
+/// The value of a header card, strings without their quotes and anything else
+/// as written
+fn card_value(value: &str) -> String {
+    let value = value.trim_start();
+
+    match value.strip_prefix('\'') {
+        Some(quoted) => {
+            let mut string = String::new();
+            let mut chars = quoted.chars().peekable();
+
+            while let Some(c) = chars.next() {
+                // Quotes inside strings are doubled
+                if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                    break;
+                }
+                string.push(c);
+            }
+
+            string.trim_end().to_string()
+        }
+        None => value.split('/').next().unwrap_or("").trim().to_string(),
+    }
+}
+
+/// Read the keywords of the header starting at the current position. None if
+/// the file ends instead.
+fn read_header(file: &mut impl Read, index: usize) -> Result<Option<HashMap<String, String>>> {
+    let mut block = [0; BLOCK_SIZE];
+    let mut keywords = HashMap::new();
+
+    match file.read_exact(&mut block) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+
+    loop {
+        for card in block.chunks(CARD_SIZE) {
+            ensure!(
+                card.is_ascii(),
+                AstroError::new(&format!("Non-ASCII header card in HDU {}", index))
+            );
+            let card = std::str::from_utf8(card)?;
+            let key = card[..8].trim_end();
+
+            if key == "END" {
+                return Ok(Some(keywords));
+            }
+            if &card[8..10] == "= " {
+                keywords.insert(key.to_string(), card_value(&card[10..]));
+            }
+        }
+
+        file.read_exact(&mut block)
+            .map_err(|_| AstroError::new(&format!("Header of HDU {} has no END", index)))?;
+    }
+}
+
+/// Walk the headers of a file's HDUs, stopping after `last` if given.
+fn read_hdus(path: &Path, last: Option<usize>) -> Result<Vec<HduInfo>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut hdus = Vec::new();
+
+    while last.is_none_or(|last| hdus.len() <= last) {
+        let index = hdus.len();
+        let Some(keywords) = read_header(&mut file, index)? else {
+            break;
+        };
+
+        let integer = |key: &str, default: i64| {
+            keywords
+                .get(key)
+                .and_then(|value| value.parse::<i64>().ok())
+                .unwrap_or(default)
+        };
+
+        let naxis = integer("NAXIS", 0);
+        ensure!(
+            (0..=999).contains(&naxis),
+            AstroError::new(&format!("Invalid NAXIS {} in HDU {}", naxis, index))
+        );
+
+        let invalid_size = || AstroError::new(&format!("Invalid data size in HDU {}", index));
+
+        let dimensions = (1..=naxis)
+            .map(|i| integer(&format!("NAXIS{}", i), 0).max(0) as usize)
+            .collect::<Vec<_>>();
+
+        let size = if dimensions.is_empty() {
+            Some(0)
+        } else {
+            dimensions
+                .iter()
+                .try_fold(1u64, |elements, &n| elements.checked_mul(n as u64))
+                .and_then(|elements| elements.checked_add(integer("PCOUNT", 0).max(0) as u64))
+                .and_then(|elements| elements.checked_mul(integer("GCOUNT", 1).max(0) as u64))
+                .and_then(|elements| elements.checked_mul(integer("BITPIX", 8).unsigned_abs() / 8))
+        }
+        .and_then(|size| size.checked_next_multiple_of(BLOCK_SIZE as u64))
+        .ok_or_else(invalid_size)?;
+
+        let hdu_type = match keywords.get("XTENSION").map(String::as_str) {
+            _ if index == 0 => HduType::Primary,
+            Some("IMAGE") => HduType::Image,
+            Some("BINTABLE") => HduType::BinTable,
+            Some("TABLE") => HduType::AsciiTable,
+            xtension => HduType::Other(xtension.unwrap_or_default().to_string()),
+        };
+
+        hdus.push(HduInfo {
+            index,
+            hdu_type,
+            dimensions,
+            name: keywords.get("EXTNAME").cloned(),
+            version: keywords.get("EXTVER").and_then(|v| v.parse().ok()),
+            data_offset: file.stream_position()?,
+        });
+
+        file.seek_relative(i64::try_from(size).map_err(|_| invalid_size())?)?;
+    }
+
+    Ok(hdus)
+}
+
+/// Describe the HDUs of a FITS file, in order.
+pub fn list_hdus(path: impl AsRef<Path>) -> Result<Vec<HduInfo>> {
+    read_hdus(path.as_ref(), None)
+}
+
+/// Offset of the data of HDU `hdu_index` in the file
+pub(super) fn data_offset(path: &Path, hdu_index: usize) -> Result<u64> {
+    Ok(read_hdus(path, Some(hdu_index))?
+        .get(hdu_index)
+        .ok_or(AstroError::new("HDU does not exist"))?
+        .data_offset)
+}
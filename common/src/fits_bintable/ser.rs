@@ -0,0 +1,392 @@
+/// Serializing table rows straight into their bytes
+use std::fmt::Display;
+
+use serde::ser::{
+    self, Impossible, Serialize, SerializeSeq, SerializeStruct, SerializeTuple,
+    SerializeTupleStruct,
+};
+
+use crate::error::AstroError;
+
+use super::{writer::ColumnDef, Element};
+
+impl ser::Error for AstroError {
+    fn custom<T: Display>(message: T) -> Self {
+        AstroError::new(&message.to_string())
+    }
+}
+
+fn error(e: anyhow::Error) -> AstroError {
+    AstroError::new(&e.to_string())
+}
+
+/// Serializes a struct as a row of `columns`, each field going to the column
+/// of the same name.
+pub struct RowSerializer<'a> {
+    columns: &'a [ColumnDef],
+    out: &'a mut Vec<u8>,
+    /// Stored bytes of each column, as their fields are serialized
+    cells: Vec<Option<Vec<u8>>>,
+}
+
+impl<'a> RowSerializer<'a> {
+    pub fn new(columns: &'a [ColumnDef], out: &'a mut Vec<u8>) -> Self {
+        Self {
+            columns,
+            out,
+            cells: vec![None; columns.len()],
+        }
+    }
+
+    fn not_a_struct() -> AstroError {
+        AstroError::new("Table rows must be structs")
+    }
+}
+
+macro_rules! unsupported {
+    ($error:expr; $($method:ident($($arg:ty),*) -> $ok:ty;)*) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<$ok, AstroError> {
+                Err($error)
+            }
+        )*
+    };
+}
+
+impl<'a> ser::Serializer for RowSerializer<'a> {
+    type Ok = ();
+    type Error = AstroError;
+    type SerializeSeq = Impossible<(), AstroError>;
+    type SerializeTuple = Impossible<(), AstroError>;
+    type SerializeTupleStruct = Impossible<(), AstroError>;
+    type SerializeTupleVariant = Impossible<(), AstroError>;
+    type SerializeMap = Impossible<(), AstroError>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), AstroError>;
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, AstroError> {
+        Ok(self)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), AstroError> {
+        value.serialize(self)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<(), AstroError> {
+        Err(Self::not_a_struct())
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), AstroError> {
+        Err(Self::not_a_struct())
+    }
+
+    unsupported! {
+        Self::not_a_struct();
+        serialize_bool(bool) -> ();
+        serialize_i8(i8) -> ();
+        serialize_i16(i16) -> ();
+        serialize_i32(i32) -> ();
+        serialize_i64(i64) -> ();
+        serialize_u8(u8) -> ();
+        serialize_u16(u16) -> ();
+        serialize_u32(u32) -> ();
+        serialize_u64(u64) -> ();
+        serialize_f32(f32) -> ();
+        serialize_f64(f64) -> ();
+        serialize_char(char) -> ();
+        serialize_str(&str) -> ();
+        serialize_bytes(&[u8]) -> ();
+        serialize_none() -> ();
+        serialize_unit() -> ();
+        serialize_unit_struct(&'static str) -> ();
+        serialize_unit_variant(&'static str, u32, &'static str) -> ();
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+}
+
+impl SerializeStruct for RowSerializer<'_> {
+    type Ok = ();
+    type Error = AstroError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), AstroError> {
+        let index = self
+            .columns
+            .iter()
+            .position(|column| column.name() == key)
+            .ok_or(AstroError::new(&format!("No column named {}", key)))?;
+
+        let mut bytes = Vec::new();
+        value.serialize(ColumnSerializer::new(&self.columns[index], &mut bytes))?;
+        self.cells[index] = Some(bytes);
+
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), AstroError> {
+        for (column, cell) in self.columns.iter().zip(self.cells) {
+            let bytes = cell.ok_or(AstroError::new(&format!(
+                "Missing value for column {}",
+                column.name()
+            )))?;
+            self.out.extend(bytes);
+        }
+
+        Ok(())
+    }
+}
+
+/// Serializes the value of a column in one row: a single element, or a
+/// sequence of exactly as many elements as the column holds.
+pub struct ColumnSerializer<'a> {
+    column: &'a ColumnDef,
+    out: &'a mut Vec<u8>,
+    /// Whether this is an element of a sequence rather than the whole value
+    in_sequence: bool,
+}
+
+impl<'a> ColumnSerializer<'a> {
+    pub fn new(column: &'a ColumnDef, out: &'a mut Vec<u8>) -> Self {
+        Self {
+            column,
+            out,
+            in_sequence: false,
+        }
+    }
+
+    fn element(self, element: Element) -> Result<(), AstroError> {
+        if !self.in_sequence && !self.column.tform().is_scalar() {
+            return Err(AstroError::new(&format!(
+                "Column {} needs {} elements per row",
+                self.column.name(),
+                self.column.elements()
+            )));
+        }
+
+        self.column.write_element(element, self.out).map_err(error)
+    }
+
+    fn sequence(self) -> Result<ElementSerializer<'a>, AstroError> {
+        if self.in_sequence || self.column.tform().datatype == 'A' {
+            return Err(AstroError::new(&format!(
+                "Column {} can't hold sequences",
+                self.column.name()
+            )));
+        }
+
+        Ok(ElementSerializer {
+            column: self.column,
+            out: self.out,
+            count: 0,
+        })
+    }
+
+    fn unsupported() -> AstroError {
+        AstroError::new("Column values must be numbers, booleans, strings or sequences")
+    }
+}
+
+impl<'a> ser::Serializer for ColumnSerializer<'a> {
+    type Ok = ();
+    type Error = AstroError;
+    type SerializeSeq = ElementSerializer<'a>;
+    type SerializeTuple = ElementSerializer<'a>;
+    type SerializeTupleStruct = ElementSerializer<'a>;
+    type SerializeTupleVariant = Impossible<(), AstroError>;
+    type SerializeMap = Impossible<(), AstroError>;
+    type SerializeStruct = Impossible<(), AstroError>;
+    type SerializeStructVariant = Impossible<(), AstroError>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), AstroError> {
+        self.element(Element::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), AstroError> {
+        self.element(Element::Integer(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), AstroError> {
+        self.element(Element::Integer(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), AstroError> {
+        self.element(Element::Integer(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), AstroError> {
+        self.element(Element::Integer(v.into()))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), AstroError> {
+        self.element(Element::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), AstroError> {
+        self.element(Element::Integer(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), AstroError> {
+        self.element(Element::Integer(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), AstroError> {
+        self.element(Element::Integer(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), AstroError> {
+        self.element(Element::Integer(v.into()))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), AstroError> {
+        self.element(Element::Float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), AstroError> {
+        self.element(Element::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), AstroError> {
+        self.element(Element::String(v.encode_utf8(&mut [0; 4])))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), AstroError> {
+        self.element(Element::String(v))
+    }
+
+    fn serialize_none(self) -> Result<(), AstroError> {
+        self.element(Element::Null)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), AstroError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), AstroError> {
+        self.element(Element::Null)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), AstroError> {
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<ElementSerializer<'a>, AstroError> {
+        self.sequence()
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<ElementSerializer<'a>, AstroError> {
+        self.sequence()
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<ElementSerializer<'a>, AstroError> {
+        self.sequence()
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), AstroError> {
+        Err(Self::unsupported())
+    }
+
+    unsupported! {
+        Self::unsupported();
+        serialize_bytes(&[u8]) -> ();
+        serialize_unit_struct(&'static str) -> ();
+        serialize_unit_variant(&'static str, u32, &'static str) -> ();
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+}
+
+/// Serializes the elements of a sequence, checking that there are as many as
+/// the column holds
+pub struct ElementSerializer<'a> {
+    column: &'a ColumnDef,
+    out: &'a mut Vec<u8>,
+    count: usize,
+}
+
+impl SerializeSeq for ElementSerializer<'_> {
+    type Ok = ();
+    type Error = AstroError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), AstroError> {
+        self.count += 1;
+
+        value.serialize(ColumnSerializer {
+            column: self.column,
+            out: self.out,
+            in_sequence: true,
+        })
+    }
+
+    fn end(self) -> Result<(), AstroError> {
+        if self.count != self.column.elements() {
+            return Err(AstroError::new(&format!(
+                "Column {} needs {} elements per row, got {}",
+                self.column.name(),
+                self.column.elements(),
+                self.count
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl SerializeTuple for ElementSerializer<'_> {
+    type Ok = ();
+    type Error = AstroError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), AstroError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), AstroError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl SerializeTupleStruct for ElementSerializer<'_> {
+    type Ok = ();
+    type Error = AstroError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), AstroError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), AstroError> {
+        SerializeSeq::end(self)
+    }
+}
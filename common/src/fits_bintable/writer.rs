@@ -0,0 +1,363 @@
+/// Writing binary tables
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{ensure, Result};
+use serde::Serialize;
+
+use crate::error::AstroError;
+
+use super::{
+    ser::{ColumnSerializer, RowSerializer},
+    Element, TForm, BLOCK_SIZE, CARD_SIZE,
+};
+
+fn card(key: &str, value: &str) -> String {
+    format!("{:<8}= {:>20}", key, value)
+}
+
+fn string_card(key: &str, value: &str) -> String {
+    format!("{:<8}= '{:<8}'", key, value.replace('\'', "''"))
+}
+
+fn padding(len: usize) -> usize {
+    (BLOCK_SIZE - len % BLOCK_SIZE) % BLOCK_SIZE
+}
+
+fn write_integer(dtype: char, n: i128, out: &mut Vec<u8>) -> Result<()> {
+    let out_of_range = || AstroError::new(&format!("{} doesn't fit in a {} column", n, dtype));
+
+    match dtype {
+        'X' | 'B' => out.extend(u8::try_from(n).map_err(|_| out_of_range())?.to_be_bytes()),
+        'I' => out.extend(i16::try_from(n).map_err(|_| out_of_range())?.to_be_bytes()),
+        'J' => out.extend(i32::try_from(n).map_err(|_| out_of_range())?.to_be_bytes()),
+        'K' => out.extend(i64::try_from(n).map_err(|_| out_of_range())?.to_be_bytes()),
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+fn write_float(dtype: char, x: f64, out: &mut Vec<u8>) {
+    match dtype {
+        'E' | 'C' => out.extend((x as f32).to_be_bytes()),
+        _ => out.extend(x.to_be_bytes()),
+    }
+}
+
+/// A column to be written: its name, format and how values are stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    name: String,
+    tform: TForm,
+    unit: Option<String>,
+    /// Values are stored as `(value - zero) / scale`, written as TSCALn and
+    /// TZEROn
+    scale: f64,
+    zero: f64,
+    /// Stored value of undefined integer elements, written as TNULLn
+    null: Option<i64>,
+}
+
+impl ColumnDef {
+    pub fn new(name: &str, format: &str) -> Result<Self> {
+        let tform = TForm::parse(format)?;
+
+        ensure!(
+            tform.heap_datatype().is_none(),
+            AstroError::new(&format!(
+                "Can't write variable-length array column {}",
+                name
+            ))
+        );
+
+        Ok(Self {
+            name: name.to_string(),
+            tform,
+            unit: None,
+            scale: 1.0,
+            zero: 0.0,
+            null: None,
+        })
+    }
+
+    pub fn with_unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_string());
+        self
+    }
+
+    /// Store values scaled, e.g. unsigned integers in signed columns with
+    /// `zero` set to the offset of the unsigned type.
+    pub fn with_scaling(mut self, scale: f64, zero: f64) -> Self {
+        self.scale = scale;
+        self.zero = zero;
+        self
+    }
+
+    pub fn with_null(mut self, null: i64) -> Self {
+        self.null = Some(null);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tform(&self) -> &TForm {
+        &self.tform
+    }
+
+    /// Number of elements in each row
+    pub(super) fn elements(&self) -> usize {
+        self.tform.width() / self.tform.element_size()
+    }
+
+    fn cards(&self, n: usize) -> Vec<String> {
+        let mut cards = vec![
+            string_card(&format!("TTYPE{}", n), &self.name),
+            string_card(&format!("TFORM{}", n), &self.tform.to_string()),
+        ];
+        if let Some(unit) = &self.unit {
+            cards.push(string_card(&format!("TUNIT{}", n), unit));
+        }
+        if self.scale != 1.0 {
+            cards.push(card(&format!("TSCAL{}", n), &self.scale.to_string()));
+        }
+        if self.zero != 0.0 {
+            cards.push(card(&format!("TZERO{}", n), &self.zero.to_string()));
+        }
+        if let Some(null) = self.null {
+            cards.push(card(&format!("TNULL{}", n), &null.to_string()));
+        }
+        cards
+    }
+
+    /// Append the stored bytes of a single element, the inverse of
+    /// [`super::FitsColumn::element`]. Strings take up the whole column and
+    /// are padded with NULs.
+    pub(super) fn write_element(&self, element: Element, out: &mut Vec<u8>) -> Result<()> {
+        let dtype = self.tform.datatype;
+        let scaled = |x: f64| (x - self.zero) / self.scale;
+
+        match (dtype, element) {
+            ('A', Element::String(s)) => {
+                ensure!(
+                    s.len() <= self.tform.repeat,
+                    AstroError::new(&format!(
+                        "{:?} is longer than the {} characters of column {}",
+                        s, self.tform.repeat, self.name
+                    ))
+                );
+                out.extend(s.as_bytes());
+                out.resize(out.len() + self.tform.repeat - s.len(), 0);
+            }
+            ('A', Element::Null) => out.resize(out.len() + self.tform.repeat, 0),
+            ('L', Element::Bool(b)) => out.push(if b { b'T' } else { b'F' }),
+            ('L', Element::Null) => out.push(0),
+            ('E' | 'D', Element::Null) => write_float(dtype, f64::NAN, out),
+            ('E' | 'D', Element::Integer(n)) => write_float(dtype, scaled(n as f64), out),
+            ('E' | 'D', Element::Float(x)) => write_float(dtype, scaled(x), out),
+            ('C' | 'M', Element::Null) => {
+                write_float(dtype, f64::NAN, out);
+                write_float(dtype, f64::NAN, out);
+            }
+            ('C' | 'M', Element::Complex(real, imaginary)) => {
+                write_float(dtype, real, out);
+                write_float(dtype, imaginary, out);
+            }
+            ('X' | 'B' | 'I' | 'J' | 'K', Element::Null) => {
+                let null = self.null.ok_or(AstroError::new(&format!(
+                    "Column {} has no TNULL for undefined values",
+                    self.name
+                )))?;
+                write_integer(dtype, null as i128, out)?;
+            }
+            ('B' | 'I' | 'J' | 'K', Element::Float(x)) if x.is_nan() => {
+                self.write_element(Element::Null, out)?
+            }
+            ('X', Element::Integer(n)) => write_integer(dtype, n, out)?,
+            ('B' | 'I' | 'J' | 'K', Element::Integer(n))
+                if self.scale == 1.0 && self.zero.fract() == 0.0 =>
+            {
+                write_integer(dtype, n - self.zero as i128, out)?
+            }
+            ('B' | 'I' | 'J' | 'K', Element::Integer(n)) => {
+                write_integer(dtype, scaled(n as f64).round() as i128, out)?
+            }
+            ('B' | 'I' | 'J' | 'K', Element::Float(x)) => {
+                write_integer(dtype, scaled(x).round() as i128, out)?
+            }
+            (_, element) => Err(AstroError::new(&format!(
+                "Can't write {:?} to column {} of type {}",
+                element, self.name, dtype
+            )))?,
+        }
+
+        Ok(())
+    }
+}
+
+/// The values of a column, encoded as they will be stored
+pub struct ColumnData {
+    def: ColumnDef,
+    data: Vec<u8>,
+    rows: usize,
+}
+
+impl ColumnData {
+    /// Encode one value per row. Values are numbers, booleans or strings for
+    /// columns with a single element per row and sequences of them otherwise,
+    /// with None for undefined values.
+    pub fn new<T: Serialize>(def: ColumnDef, values: impl IntoIterator<Item = T>) -> Result<Self> {
+        let mut data = Vec::new();
+        let mut rows = 0;
+
+        for value in values {
+            value.serialize(ColumnSerializer::new(&def, &mut data))?;
+            rows += 1;
+        }
+
+        Ok(Self { def, data, rows })
+    }
+
+    pub fn def(&self) -> &ColumnDef {
+        &self.def
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+}
+
+/// Writes a FITS file made of an empty primary HDU followed by binary table
+/// extensions
+pub struct FitsTableWriter<W: Write> {
+    out: W,
+}
+
+impl FitsTableWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> FitsTableWriter<W> {
+    /// Start the file by writing the primary HDU.
+    pub fn new(out: W) -> Result<Self> {
+        let mut writer = Self { out };
+
+        writer.write_header(&[
+            card("SIMPLE", "T"),
+            card("BITPIX", "8"),
+            card("NAXIS", "0"),
+            card("EXTEND", "T"),
+        ])?;
+
+        Ok(writer)
+    }
+
+    fn write_header(&mut self, cards: &[String]) -> Result<()> {
+        let mut header = String::new();
+        for card in cards.iter().map(String::as_str).chain(["END"]) {
+            ensure!(
+                card.len() <= CARD_SIZE,
+                AstroError::new(&format!("Header card too long: {}", card))
+            );
+            header.push_str(&format!("{:<CARD_SIZE$}", card));
+        }
+        header.push_str(&" ".repeat(padding(header.len())));
+
+        self.out.write_all(header.as_bytes())?;
+        Ok(())
+    }
+
+    fn write_table(
+        &mut self,
+        extname: &str,
+        columns: &[&ColumnDef],
+        rows: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        let row_size = columns.iter().map(|c| c.tform.width()).sum::<usize>();
+
+        let mut cards = vec![
+            string_card("XTENSION", "BINTABLE"),
+            card("BITPIX", "8"),
+            card("NAXIS", "2"),
+            card("NAXIS1", &row_size.to_string()),
+            card("NAXIS2", &rows.to_string()),
+            card("PCOUNT", "0"),
+            card("GCOUNT", "1"),
+            card("TFIELDS", &columns.len().to_string()),
+        ];
+        for (i, column) in columns.iter().enumerate() {
+            cards.extend(column.cards(i + 1));
+        }
+        cards.push(string_card("EXTNAME", extname));
+
+        self.write_header(&cards)?;
+        self.out.write_all(data)?;
+        self.out.write_all(&vec![0; padding(data.len())])?;
+
+        Ok(())
+    }
+
+    /// Write a table with one row per item of `rows`. Rows are structs whose
+    /// fields are named like the columns, with values as in [`ColumnData::new`].
+    pub fn write_rows<T: Serialize>(
+        &mut self,
+        extname: &str,
+        columns: &[ColumnDef],
+        rows: impl IntoIterator<Item = T>,
+    ) -> Result<()> {
+        let mut data = Vec::new();
+        let mut n_rows = 0;
+
+        for row in rows {
+            row.serialize(RowSerializer::new(columns, &mut data))?;
+            n_rows += 1;
+        }
+
+        self.write_table(extname, &columns.iter().collect::<Vec<_>>(), n_rows, &data)
+    }
+
+    /// Write a table from the values of each column, which must all have the
+    /// same number of rows.
+    pub fn write_columns(&mut self, extname: &str, columns: &[ColumnData]) -> Result<()> {
+        let rows = columns.first().map_or(0, ColumnData::len);
+
+        for column in columns {
+            ensure!(
+                column.rows == rows,
+                AstroError::new(&format!(
+                    "Column {} has {} rows instead of {}",
+                    column.def.name, column.rows, rows
+                ))
+            );
+        }
+
+        let mut data = Vec::new();
+        for row in 0..rows {
+            for column in columns {
+                let width = column.def.tform.width();
+                data.extend(&column.data[row * width..(row + 1) * width]);
+            }
+        }
+
+        let defs = columns.iter().map(|c| &c.def).collect::<Vec<_>>();
+        self.write_table(extname, &defs, rows, &data)
+    }
+
+    /// Flush the file, returning the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
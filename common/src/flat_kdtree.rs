@@ -0,0 +1,111 @@
+/// kd-trees stored implicitly in flat arrays
+///
+/// A slice of points is in kd-tree order if its median along the first axis
+/// is at the middle index, with the points before it no greater along that
+/// axis and the points after it no smaller, and the same holds recursively for
+/// both halves along the next axis. This needs no node structure at all, so
+/// the points can be written to disk as-is and searched straight from a
+/// memory map.
+///
+/// Compute the permutation that puts `points` into kd-tree order:
+/// `points[order[i]]` is the i-th point of the tree.
+pub fn kd_order<const K: usize>(points: &[[f64; K]]) -> Vec<usize> {
+    let mut order = (0..points.len()).collect::<Vec<_>>();
+    sort(points, &mut order, 0);
+    order
+}
+
+fn sort<const K: usize>(points: &[[f64; K]], order: &mut [usize], axis: usize) {
+    if order.len() <= 1 {
+        return;
+    }
+
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |&a, &b| points[a][axis].total_cmp(&points[b][axis]));
+
+    let (left, right) = order.split_at_mut(mid);
+    sort(points, left, (axis + 1) % K);
+    sort(points, &mut right[1..], (axis + 1) % K);
+}
+
+/// Indices of all points of a tree in kd-tree order within `radius` of `query`.
+pub fn within_radius<const K: usize>(
+    points: &[[f64; K]],
+    query: &[f64; K],
+    radius: f64,
+) -> Vec<usize> {
+    let mut found = Vec::new();
+    search(points, 0, query, radius, 0, &mut found);
+    found
+}
+
+fn search<const K: usize>(
+    points: &[[f64; K]],
+    offset: usize,
+    query: &[f64; K],
+    radius: f64,
+    axis: usize,
+    found: &mut Vec<usize>,
+) {
+    if points.is_empty() {
+        return;
+    }
+
+    let mid = points.len() / 2;
+    let point = &points[mid];
+
+    let squared_distance = (0..K).map(|i| (point[i] - query[i]).powi(2)).sum::<f64>();
+    if squared_distance <= radius * radius {
+        found.push(offset + mid);
+    }
+
+    let diff = query[axis] - point[axis];
+    let next_axis = (axis + 1) % K;
+
+    if diff <= radius {
+        search(&points[..mid], offset, query, radius, next_axis, found);
+    }
+    if diff >= -radius {
+        search(
+            &points[mid + 1..],
+            offset + mid + 1,
+            query,
+            radius,
+            next_axis,
+            found,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn prop_within_radius(
+            points in prop::collection::vec(prop::array::uniform3(-1.0..1.0), 0..300),
+            query in prop::array::uniform3(-1.0..1.0),
+            radius in 0.0..1.0,
+        ) {
+            let order = kd_order(&points);
+            let tree = order.iter().map(|&i| points[i]).collect::<Vec<_>>();
+
+            let mut found = within_radius(&tree, &query, radius)
+                .into_iter()
+                .map(|i| order[i])
+                .collect::<Vec<_>>();
+            found.sort();
+
+            let expected = (0..points.len())
+                .filter(|&i| {
+                    (0..3).map(|k| (points[i][k] - query[k]).powi(2)).sum::<f64>() <= radius * radius
+                })
+                .collect::<Vec<_>>();
+
+            prop_assert_eq!(found, expected);
+        }
+    }
+}
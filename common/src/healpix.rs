@@ -0,0 +1,576 @@
+/// HEALPix sky tessellation
+/// Ported from the relevant parts of healpix_base
+///
+/// Positions are unit vectors or (RA, Dec) in degrees. `nside` must always be
+/// a power of two, the ring scheme is implemented on top of the nested one.
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use nalgebra::Vector3;
+
+use crate::sphere::{angular_distance, radec_to_xyz, xyz_to_radec};
+
+// Ring and longitude offsets of the twelve base faces
+const JRLL: [i64; 12] = [2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4];
+const JPLL: [i64; 12] = [1, 3, 5, 7, 0, 2, 4, 6, 1, 3, 5, 7];
+
+// Neighbour offsets, in the order SW, W, NW, N, NE, E, SE, S
+const X_OFFSET: [i64; 8] = [-1, -1, 0, 1, 1, 1, 0, -1];
+const Y_OFFSET: [i64; 8] = [0, 1, 1, 1, 0, -1, -1, -1];
+
+// Face a neighbour lies on when stepping off a face, by direction and current
+// face, or -1 where three faces meet and there is nothing in that direction
+const FACE_ARRAY: [[i64; 12]; 9] = [
+    [8, 9, 10, 11, -1, -1, -1, -1, 10, 11, 8, 9],
+    [5, 6, 7, 4, 8, 9, 10, 11, 9, 10, 11, 8],
+    [-1, -1, -1, -1, 5, 6, 7, 4, -1, -1, -1, -1],
+    [4, 5, 6, 7, 11, 8, 9, 10, 11, 8, 9, 10],
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+    [1, 2, 3, 0, 0, 1, 2, 3, 5, 6, 7, 4],
+    [-1, -1, -1, -1, 7, 4, 5, 6, -1, -1, -1, -1],
+    [3, 0, 1, 2, 3, 0, 1, 2, 4, 5, 6, 7],
+    [2, 3, 0, 1, -1, -1, -1, -1, 0, 1, 2, 3],
+];
+
+// Coordinate transform when stepping onto a neighbouring face, by direction
+// and row of faces: bit 0 flips x, bit 1 flips y, bit 2 swaps x and y
+const SWAP_ARRAY: [[i64; 3]; 9] = [
+    [0, 0, 3],
+    [0, 0, 6],
+    [0, 0, 0],
+    [0, 0, 5],
+    [0, 0, 0],
+    [5, 0, 0],
+    [0, 0, 0],
+    [6, 0, 0],
+    [3, 0, 0],
+];
+
+pub fn npix(nside: u32) -> u64 {
+    12 * nside as u64 * nside as u64
+}
+
+/// Interleave the bits of v with zeros: 0b111 -> 0b10101
+fn spread_bits(v: u64) -> u64 {
+    let mut v = v & 0xffff_ffff;
+    v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+    v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+    v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+    (v | (v << 1)) & 0x5555_5555_5555_5555
+}
+
+/// Inverse of spread_bits, ignoring the odd bits
+fn compress_bits(v: u64) -> u64 {
+    let mut v = v & 0x5555_5555_5555_5555;
+    v = (v | (v >> 1)) & 0x3333_3333_3333_3333;
+    v = (v | (v >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | (v >> 4)) & 0x00ff_00ff_00ff_00ff;
+    v = (v | (v >> 8)) & 0x0000_ffff_0000_ffff;
+    (v | (v >> 16)) & 0x0000_0000_ffff_ffff
+}
+
+fn xyf_to_nested(nside: u32, x: i64, y: i64, face: i64) -> u64 {
+    face as u64 * nside as u64 * nside as u64 + spread_bits(x as u64) + (spread_bits(y as u64) << 1)
+}
+
+fn nested_to_xyf(nside: u32, pixel: u64) -> (i64, i64, i64) {
+    let npface = nside as u64 * nside as u64;
+    let p = pixel % npface;
+
+    (
+        compress_bits(p) as i64,
+        compress_bits(p >> 1) as i64,
+        (pixel / npface) as i64,
+    )
+}
+
+fn xyf_to_ring(nside: u32, x: i64, y: i64, face: i64) -> u64 {
+    let ns = nside as i64;
+    let nl4 = 4 * ns;
+    let ncap = 2 * ns * (ns - 1);
+
+    let jr = JRLL[face as usize] * ns - x - y - 1;
+
+    let (nr, n_before, kshift) = if jr < ns {
+        (jr, 2 * jr * (jr - 1), 0)
+    } else if jr > 3 * ns {
+        let nr = nl4 - jr;
+        (nr, npix(nside) as i64 - 2 * (nr + 1) * nr, 0)
+    } else {
+        (ns, ncap + (jr - ns) * nl4, (jr - ns) & 1)
+    };
+
+    let mut jp = (JPLL[face as usize] * nr + x - y + 1 + kshift) / 2;
+    if jp > nl4 {
+        jp -= nl4;
+    } else if jp < 1 {
+        jp += nl4;
+    }
+
+    (n_before + jp - 1) as u64
+}
+
+fn ring_to_xyf(nside: u32, pixel: u64) -> (i64, i64, i64) {
+    let ns = nside as i64;
+    let nl2 = 2 * ns;
+    let nl4 = 4 * ns;
+    let ncap = 2 * ns * (ns - 1);
+    let npix = npix(nside) as i64;
+    let pix = pixel as i64;
+
+    let isqrt = |v: i64| (v as f64).sqrt() as i64;
+
+    let (iring, iphi, kshift, nr, face) = if pix < ncap {
+        // North polar cap
+        let iring = (1 + isqrt(1 + 2 * pix)) >> 1;
+        let iphi = pix + 1 - 2 * iring * (iring - 1);
+
+        (iring, iphi, 0, iring, (iphi - 1) / iring)
+    } else if pix < npix - ncap {
+        // Equatorial region
+        let ip = pix - ncap;
+        let tmp = ip / nl4;
+        let iring = tmp + ns;
+        let iphi = ip - tmp * nl4 + 1;
+        let kshift = (iring + ns) & 1;
+
+        let ire = tmp + 1;
+        let irm = nl2 + 1 - tmp;
+        let ifm = (iphi - ire / 2 + ns - 1) / ns;
+        let ifp = (iphi - irm / 2 + ns - 1) / ns;
+
+        let face = if ifp == ifm {
+            ifp | 4
+        } else if ifp < ifm {
+            ifp
+        } else {
+            ifm + 8
+        };
+
+        (iring, iphi, kshift, ns, face)
+    } else {
+        // South polar cap
+        let ip = npix - pix;
+        let iring = (1 + isqrt(2 * ip - 1)) >> 1;
+        let iphi = 4 * iring + 1 - (ip - 2 * iring * (iring - 1));
+
+        (2 * nl2 - iring, iphi, 0, iring, 8 + (iphi - 1) / iring)
+    };
+
+    let irt = iring - JRLL[face as usize] * ns + 1;
+    let mut ipt = 2 * iphi - JPLL[face as usize] * nr - kshift - 1;
+    if ipt >= nl2 {
+        ipt -= 8 * ns;
+    }
+
+    ((ipt - irt) >> 1, (-ipt - irt) >> 1, face)
+}
+
+/// Position of the point with continuous coordinates (x, y) in [0, 1] on a
+/// base face, (0.5, 0.5) being the center of the face.
+fn xyf_to_vec(x: f64, y: f64, face: i64) -> Vector3<f64> {
+    let jr = JRLL[face as usize] as f64 - x - y;
+
+    let (nr, z) = if jr < 1.0 {
+        (jr, 1.0 - jr * jr / 3.0)
+    } else if jr > 3.0 {
+        let nr = 4.0 - jr;
+        (nr, nr * nr / 3.0 - 1.0)
+    } else {
+        (1.0, (2.0 - jr) * 2.0 / 3.0)
+    };
+
+    let tmp = (JPLL[face as usize] as f64 * nr + x - y).rem_euclid(8.0);
+    let phi = if nr < 1e-15 {
+        0.0
+    } else {
+        0.5 * FRAC_PI_2 * tmp / nr
+    };
+
+    let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+
+    Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), z)
+}
+
+pub fn nest2ring(nside: u32, pixel: u64) -> u64 {
+    let (x, y, face) = nested_to_xyf(nside, pixel);
+    xyf_to_ring(nside, x, y, face)
+}
+
+pub fn ring2nest(nside: u32, pixel: u64) -> u64 {
+    let (x, y, face) = ring_to_xyf(nside, pixel);
+    xyf_to_nested(nside, x, y, face)
+}
+
+/// Pixel containing `point` in the nested scheme.
+pub fn vec2pix_nested(nside: u32, point: &Vector3<f64>) -> u64 {
+    let point = point.normalize();
+    let z = point[2];
+    let za = z.abs();
+    let ns = nside as i64;
+
+    // Longitude in units of 90°, in [0, 4)
+    let tt = (point[1].atan2(point[0]) / FRAC_PI_2).rem_euclid(4.0);
+
+    let (x, y, face) = if za <= 2.0 / 3.0 {
+        // Equatorial region
+        let temp1 = nside as f64 * (0.5 + tt);
+        let temp2 = nside as f64 * z * 0.75;
+        let jp = (temp1 - temp2) as i64;
+        let jm = (temp1 + temp2) as i64;
+        let ifp = jp / ns;
+        let ifm = jm / ns;
+
+        let face = if ifp == ifm {
+            ifp | 4
+        } else if ifp < ifm {
+            ifp
+        } else {
+            ifm + 8
+        };
+
+        (jm % ns, ns - jp % ns - 1, face)
+    } else {
+        // Polar caps
+        let ntt = (tt as i64).min(3);
+        let tp = tt - ntt as f64;
+        let tmp = nside as f64 * (3.0 * (1.0 - za)).sqrt();
+        let jp = ((tp * tmp) as i64).min(ns - 1);
+        let jm = (((1.0 - tp) * tmp) as i64).min(ns - 1);
+
+        if z >= 0.0 {
+            (ns - jm - 1, ns - jp - 1, ntt)
+        } else {
+            (jp, jm, ntt + 8)
+        }
+    };
+
+    xyf_to_nested(nside, x, y, face)
+}
+
+/// Pixel containing `point` in the ring scheme.
+pub fn vec2pix_ring(nside: u32, point: &Vector3<f64>) -> u64 {
+    nest2ring(nside, vec2pix_nested(nside, point))
+}
+
+/// Pixel containing (RA, Dec) in the nested scheme.
+pub fn ang2pix_nested(nside: u32, ra: f64, dec: f64) -> u64 {
+    vec2pix_nested(nside, &radec_to_xyz(ra, dec))
+}
+
+/// Pixel containing (RA, Dec) in the ring scheme.
+pub fn ang2pix_ring(nside: u32, ra: f64, dec: f64) -> u64 {
+    vec2pix_ring(nside, &radec_to_xyz(ra, dec))
+}
+
+/// Unit vector pointing at the center of `pixel` in the nested scheme.
+pub fn pix2vec_nested(nside: u32, pixel: u64) -> Vector3<f64> {
+    let (x, y, face) = nested_to_xyf(nside, pixel);
+    let ns = nside as f64;
+
+    xyf_to_vec((x as f64 + 0.5) / ns, (y as f64 + 0.5) / ns, face)
+}
+
+/// Unit vector pointing at the center of `pixel` in the ring scheme.
+pub fn pix2vec_ring(nside: u32, pixel: u64) -> Vector3<f64> {
+    pix2vec_nested(nside, ring2nest(nside, pixel))
+}
+
+/// (RA, Dec) of the center of `pixel` in the nested scheme.
+pub fn pix2ang_nested(nside: u32, pixel: u64) -> (f64, f64) {
+    xyz_to_radec(&pix2vec_nested(nside, pixel))
+}
+
+/// (RA, Dec) of the center of `pixel` in the ring scheme.
+pub fn pix2ang_ring(nside: u32, pixel: u64) -> (f64, f64) {
+    xyz_to_radec(&pix2vec_ring(nside, pixel))
+}
+
+/// Pixels adjacent to `pixel` in the nested scheme, in the order SW, W, NW,
+/// N, NE, E, SE, S. At the eight points where only three base faces meet,
+/// the pixels touching them lack one of the diagonal neighbours.
+pub fn neighbours_nested(nside: u32, pixel: u64) -> [Option<u64>; 8] {
+    let (ix, iy, face) = nested_to_xyf(nside, pixel);
+    let ns = nside as i64;
+
+    let mut neighbours = [None; 8];
+
+    for (i, neighbour) in neighbours.iter_mut().enumerate() {
+        let mut x = ix + X_OFFSET[i];
+        let mut y = iy + Y_OFFSET[i];
+
+        // Direction of the face the neighbour is on, 4 being this face
+        let mut direction = 4;
+
+        if x < 0 {
+            x += ns;
+            direction -= 1;
+        } else if x >= ns {
+            x -= ns;
+            direction += 1;
+        }
+
+        if y < 0 {
+            y += ns;
+            direction -= 3;
+        } else if y >= ns {
+            y -= ns;
+            direction += 3;
+        }
+
+        let f = FACE_ARRAY[direction][face as usize];
+        if f < 0 {
+            continue;
+        }
+
+        let bits = SWAP_ARRAY[direction][face as usize >> 2];
+        if bits & 1 != 0 {
+            x = ns - x - 1;
+        }
+        if bits & 2 != 0 {
+            y = ns - y - 1;
+        }
+        if bits & 4 != 0 {
+            (x, y) = (y, x);
+        }
+
+        *neighbour = Some(xyf_to_nested(nside, x, y, f));
+    }
+
+    neighbours
+}
+
+/// Pixels adjacent to `pixel` in the ring scheme, see [`neighbours_nested`].
+pub fn neighbours_ring(nside: u32, pixel: u64) -> [Option<u64>; 8] {
+    neighbours_nested(nside, ring2nest(nside, pixel)).map(|n| n.map(|n| nest2ring(nside, n)))
+}
+
+/// `4 * step` points along the boundary of `pixel` in the nested scheme,
+/// going counterclockwise from its northernmost corner. With `step` = 1,
+/// these are the corners N, W, S and E.
+pub fn boundaries_nested(nside: u32, pixel: u64, step: usize) -> Vec<Vector3<f64>> {
+    let (ix, iy, face) = nested_to_xyf(nside, pixel);
+    let ns = nside as f64;
+
+    let dc = 0.5 / ns;
+    let xc = (ix as f64 + 0.5) / ns;
+    let yc = (iy as f64 + 0.5) / ns;
+    let d = 1.0 / (step as f64 * ns);
+
+    let mut points = Vec::with_capacity(4 * step);
+
+    for i in 0..step {
+        points.push(xyf_to_vec(xc + dc - i as f64 * d, yc + dc, face));
+    }
+    for i in 0..step {
+        points.push(xyf_to_vec(xc - dc, yc + dc - i as f64 * d, face));
+    }
+    for i in 0..step {
+        points.push(xyf_to_vec(xc - dc + i as f64 * d, yc - dc, face));
+    }
+    for i in 0..step {
+        points.push(xyf_to_vec(xc + dc, yc - dc + i as f64 * d, face));
+    }
+
+    points
+}
+
+/// Points along the boundary of `pixel` in the ring scheme, see [`boundaries_nested`].
+pub fn boundaries_ring(nside: u32, pixel: u64, step: usize) -> Vec<Vector3<f64>> {
+    boundaries_nested(nside, ring2nest(nside, pixel), step)
+}
+
+/// Upper bound on the angle in radians between the center of any pixel
+/// and any point inside it.
+pub fn max_pixel_radius(nside: u32) -> f64 {
+    let ns = nside as f64;
+
+    let phi = PI / (4.0 * ns);
+    let a = Vector3::new(
+        (5.0f64 / 9.0).sqrt() * phi.cos(),
+        (5.0f64 / 9.0).sqrt() * phi.sin(),
+        2.0 / 3.0,
+    );
+
+    let z = 1.0 - (1.0 - 1.0 / ns).powi(2) / 3.0;
+    let b = Vector3::new((1.0 - z * z).sqrt(), 0.0, z);
+
+    angular_distance(&a, &b)
+}
+
+/// Pixels in the nested scheme overlapping the disc of `radius` radians
+/// around `center`. A few pixels just outside the disc may be included.
+pub fn query_disc_nested(nside: u32, center: &Vector3<f64>, radius: f64) -> Vec<u64> {
+    let center = center.normalize();
+    let mut pixels = (0..12).collect::<Vec<u64>>();
+    let mut level = 1;
+
+    // Descend the hierarchy, keeping the children of all pixels that may overlap
+    loop {
+        // Small margin for rounding errors
+        let pixel_radius = max_pixel_radius(level) * 1.01;
+
+        pixels.retain(|&p| {
+            angular_distance(&center, &pix2vec_nested(level, p)) <= radius + pixel_radius
+        });
+
+        if level >= nside {
+            break;
+        }
+
+        pixels = pixels.into_iter().flat_map(|p| 4 * p..4 * p + 4).collect();
+        level *= 2;
+    }
+
+    pixels
+}
+
+/// Pixels in the ring scheme overlapping a disc, see [`query_disc_nested`].
+pub fn query_disc_ring(nside: u32, center: &Vector3<f64>, radius: f64) -> Vec<u64> {
+    let mut pixels = query_disc_nested(nside, center, radius)
+        .into_iter()
+        .map(|p| nest2ring(nside, p))
+        .collect::<Vec<_>>();
+
+    pixels.sort();
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    const NSIDES: [u32; 5] = [1, 2, 4, 8, 16];
+
+    #[test]
+    fn test_nested_roundtrip() {
+        for nside in NSIDES {
+            for pixel in 0..npix(nside) {
+                assert_eq!(vec2pix_nested(nside, &pix2vec_nested(nside, pixel)), pixel);
+
+                let (ra, dec) = pix2ang_nested(nside, pixel);
+                assert_eq!(ang2pix_nested(nside, ra, dec), pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ring_scheme() {
+        for nside in NSIDES {
+            let ring = (0..npix(nside))
+                .map(|p| nest2ring(nside, p))
+                .collect::<HashSet<_>>();
+            assert_eq!(ring.len() as u64, npix(nside));
+
+            let mut last = (0.0, 90.0);
+
+            for pixel in 0..npix(nside) {
+                assert_eq!(nest2ring(nside, ring2nest(nside, pixel)), pixel);
+                assert_eq!(vec2pix_ring(nside, &pix2vec_ring(nside, pixel)), pixel);
+
+                // Rings go from north to south, pixels eastward within each ring
+                let (ra, dec) = pix2ang_ring(nside, pixel);
+                assert!(dec <= last.1 + 1e-9);
+                if (dec - last.1).abs() < 1e-9 {
+                    assert!(ra > last.0);
+                }
+                last = (ra, dec);
+            }
+        }
+
+        assert_eq!(ang2pix_ring(4, 10.0, 89.0), 0);
+        assert_eq!(ang2pix_ring(4, 350.0, -89.0), npix(4) - 1);
+    }
+
+    #[test]
+    fn test_known_pixels() {
+        // North pole, first quadrant: first pixel of face 0
+        assert_eq!(vec2pix_nested(1, &Vector3::new(0.1, 0.1, 1.0)), 0);
+        // South pole, third quadrant: face 10
+        assert_eq!(vec2pix_nested(1, &Vector3::new(-0.1, -0.1, -1.0)), 10);
+        // Equator at RA 0 is the center of face 4
+        assert_eq!(vec2pix_nested(1, &Vector3::x()), 4);
+        assert!((pix2vec_nested(1, 4) - Vector3::x()).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_neighbours() {
+        for nside in [2, 4, 8, 16] {
+            let mut missing = 0;
+
+            for pixel in 0..npix(nside) {
+                let neighbours = neighbours_nested(nside, pixel);
+                missing += neighbours.iter().filter(|n| n.is_none()).count();
+
+                for &neighbour in neighbours.iter().flatten() {
+                    assert_ne!(neighbour, pixel);
+                    assert!(neighbours_nested(nside, neighbour).contains(&Some(pixel)));
+                }
+
+                // Points just outside each corner lie in a neighbouring pixel
+                let center = pix2vec_nested(nside, pixel);
+                for corner in boundaries_nested(nside, pixel, 1) {
+                    let outside = vec2pix_nested(nside, &(corner * 1.01 - center * 0.01));
+                    assert!(outside == pixel || neighbours.contains(&Some(outside)));
+                }
+
+                let ring = nest2ring(nside, pixel);
+                assert_eq!(
+                    neighbours_ring(nside, ring),
+                    neighbours.map(|n| n.map(|n| nest2ring(nside, n)))
+                );
+            }
+
+            // Each of the 8 points where three faces meet is touched by 3 pixels
+            assert_eq!(missing, 24);
+        }
+    }
+
+    #[test]
+    fn test_boundaries() {
+        for nside in NSIDES {
+            let pixel_radius = max_pixel_radius(nside);
+
+            for pixel in 0..npix(nside) {
+                let center = pix2vec_nested(nside, pixel);
+
+                for point in boundaries_nested(nside, pixel, 4) {
+                    assert!(angular_distance(&center, &point) <= pixel_radius + 1e-12);
+
+                    // Points just inside the boundary belong to the pixel
+                    let inside = point * 0.99 + center * 0.01;
+                    assert_eq!(vec2pix_nested(nside, &inside), pixel);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_query_disc() {
+        let nside = 16;
+
+        for (ra, dec, radius) in [(0.0, -90.0, 1.0), (359.0, 0.0, 5.0), (123.0, 45.0, 20.0)] {
+            let center = radec_to_xyz(ra, dec);
+            let radius = f64::to_radians(radius);
+            let pixels = query_disc_nested(nside, &center, radius);
+
+            assert!(pixels.contains(&vec2pix_nested(nside, &center)));
+
+            for pixel in 0..npix(nside) {
+                let distance = angular_distance(&center, &pix2vec_nested(nside, pixel));
+
+                if distance <= radius {
+                    assert!(pixels.contains(&pixel));
+                }
+                if pixels.contains(&pixel) {
+                    assert!(distance <= radius + 1.01 * max_pixel_radius(nside));
+                }
+            }
+
+            let ring = query_disc_ring(nside, &center, radius);
+            assert_eq!(ring.len(), pixels.len());
+        }
+    }
+}
@@ -1,46 +1,534 @@
+use std::{fmt::Debug, path::Path};
+
+use anyhow::Result;
 use kd_tree::KdTree;
+use nalgebra::Vector3;
 use serde::{Deserialize, Serialize};
 
-use crate::quad::Quad;
+use crate::{
+    healpix::{max_pixel_radius, npix, pix2vec_nested, vec2pix_nested},
+    mapped_index::{self, MappedIndex},
+    quad::{code_tolerance, GHash, Quad},
+    quad_builder::QuadFilter,
+    sphere::{angular_distance, chord_length, radec_to_xyz, xyz_to_radec},
+    star_id::StarId,
+};
+
+/// How many times the expected code error to search around an image quad's
+/// hash. The error is roughly chi-distributed with 4 degrees of freedom, so
+/// twice its RMS covers over 99% of true matches.
+pub(crate) const CODE_TOLERANCE_FACTOR: f64 = 2.0;
+
+/// Proper motion of a star and the reference epoch of its catalog position
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Motion {
+    /// Proper motion in RA (times cos Dec) and in Dec, in arcseconds per year
+    pub proper_motion: [f32; 2],
+    /// Year the catalog position is given for, e.g. 2000.0. This is not
+    /// necessarily when the star was observed: USNO-B positions are at J2000
+    /// whatever the mean epoch of their observations.
+    pub epoch: f32,
+}
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+impl Default for Motion {
+    /// A star that doesn't move, with its position at J2000
+    fn default() -> Self {
+        Self {
+            proper_motion: [0.0, 0.0],
+            epoch: 2000.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct IndexStar {
-    designation: String,
+    id: StarId,
     position: [f64; 2],
+    mag: f32,
+    motion: Motion,
+    sweep: u32,
+}
+
+impl IndexStar {
+    /// `position` is (RA, Dec) in degrees, `mag` the magnitude in the index's
+    /// band and `sweep` the uniformization sweep the star was selected in.
+    pub fn new(id: StarId, position: [f64; 2], mag: f32, motion: Motion, sweep: u32) -> Self {
+        Self {
+            id,
+            position,
+            mag,
+            motion,
+            sweep,
+        }
+    }
+
+    pub fn id(&self) -> StarId {
+        self.id
+    }
+
+    /// Catalog designation of the star, e.g. "0000-0000065"
+    pub fn designation(&self) -> String {
+        self.id.to_string()
+    }
+
+    pub fn position(&self) -> [f64; 2] {
+        self.position
+    }
+
+    /// Magnitude in the band of the index, see [`IndexMetadata::band`].
+    pub fn mag(&self) -> f32 {
+        self.mag
+    }
+
+    pub fn motion(&self) -> Motion {
+        self.motion
+    }
+
+    /// Stars of sweep 0 are the brightest of their HEALPix cell, those of
+    /// sweep 1 the second brightest, and so on. Stars of early sweeps are
+    /// spread evenly over the sky and the most likely to be in an image.
+    pub fn sweep(&self) -> u32 {
+        self.sweep
+    }
+
+    pub fn xyz(&self) -> Vector3<f64> {
+        radec_to_xyz(self.position[0], self.position[1])
+    }
+
+    /// Position as a unit vector at `epoch` (a year, e.g. 2024.5), moved
+    /// along the tangent plane by the star's proper motion.
+    pub fn xyz_at(&self, epoch: f64) -> Vector3<f64> {
+        let [ra, dec] = self.position.map(f64::to_radians);
+        let (sin_ra, cos_ra) = ra.sin_cos();
+        let (sin_dec, cos_dec) = dec.sin_cos();
+
+        let east = Vector3::new(-sin_ra, cos_ra, 0.0);
+        let north = Vector3::new(-sin_dec * cos_ra, -sin_dec * sin_ra, cos_dec);
+
+        let years = epoch - self.motion.epoch as f64;
+        let [pm_ra, pm_dec] = self
+            .motion
+            .proper_motion
+            .map(|pm| (pm as f64 / 3600.0).to_radians() * years);
+
+        (self.xyz() + east * pm_ra + north * pm_dec).normalize()
+    }
+
+    /// (RA, Dec) in degrees at `epoch`, see [`Self::xyz_at`].
+    pub fn position_at(&self, epoch: f64) -> [f64; 2] {
+        let (ra, dec) = xyz_to_radec(&self.xyz_at(epoch));
+        [ra, dec]
+    }
+}
+
+/// A HEALPix pixel (nested scheme) of the sky covered by an index
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct SkyTile {
+    pub nside: u32,
+    pub pixel: u64,
+}
+
+impl SkyTile {
+    pub fn center(&self) -> Vector3<f64> {
+        pix2vec_nested(self.nside, self.pixel)
+    }
+
+    /// Upper bound on the distance in radians between the center and any
+    /// point of the tile.
+    pub fn radius(&self) -> f64 {
+        max_pixel_radius(self.nside)
+    }
+
+    pub fn contains(&self, point: &Vector3<f64>) -> bool {
+        vec2pix_nested(self.nside, point) == self.pixel
+    }
+
+    /// The tile of the coarser resolution `nside` containing this one, None
+    /// if `nside` is finer than the tile's.
+    pub fn parent(&self, nside: u32) -> Option<SkyTile> {
+        (nside <= self.nside).then(|| SkyTile {
+            nside,
+            pixel: self.pixel / (npix(self.nside) / npix(nside)),
+        })
+    }
+
+    /// Whether `cell` of the finer resolution `nside` lies in this tile.
+    pub fn contains_cell(&self, nside: u32, cell: u64) -> bool {
+        nside >= self.nside && cell / (npix(nside) / npix(self.nside)) == self.pixel
+    }
+
+    /// Whether the tile may overlap the disc of `radius` radians around `center`.
+    pub fn overlaps_disc(&self, center: &Vector3<f64>, radius: f64) -> bool {
+        angular_distance(&self.center(), center) <= self.radius() + radius
+    }
+}
+
+/// Parameters an index was built with
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct IndexMetadata {
+    pub nside: u32,
+    /// Range of quad scales (distance between A and B) in arcminutes
+    pub scale_range: (f64, f64),
+    /// Catalog the stars were taken from, e.g. "USNO-B1.0"
+    pub catalog: String,
+    /// Photometric band the stars were selected by, e.g. "R"
+    pub band: String,
+    /// Part of the sky the index's quads lie in, None if it covers the whole sky
+    #[serde(default)]
+    pub tile: Option<SkyTile>,
+    /// For indexes merged from tiles that don't make up all of `tile`, the
+    /// tiles that were merged. `tile` then only encloses them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_tiles: Vec<SkyTile>,
+    /// How the stars and quads were selected, None for indexes that don't
+    /// record it
+    #[serde(default)]
+    pub settings: Option<BuildSettings>,
+}
+
+/// The remaining inputs of an index build, recorded so that an existing
+/// index can be told apart from one the same inputs would build
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct BuildSettings {
+    /// Faintest magnitude of the catalog stars considered
+    pub max_mag: f32,
+    pub uniformize_nside: u32,
+    pub stars_per_cell: usize,
+    pub quads_per_cell: usize,
+    pub filter: QuadFilter,
+}
+
+impl IndexMetadata {
+    /// The tiles the index's quads lie in, None if it covers the whole sky
+    pub fn coverage(&self) -> Option<Vec<SkyTile>> {
+        if self.merged_tiles.is_empty() {
+            self.tile.map(|tile| vec![tile])
+        } else {
+            Some(self.merged_tiles.clone())
+        }
+    }
+
+    pub fn covers(&self, point: &Vector3<f64>) -> bool {
+        self.coverage()
+            .is_none_or(|tiles| tiles.iter().any(|tile| tile.contains(point)))
+    }
+
+    /// Whether the index may have quads within `radius` radians of `center`.
+    pub fn overlaps_disc(&self, center: &Vector3<f64>, radius: f64) -> bool {
+        self.coverage()
+            .is_none_or(|tiles| tiles.iter().any(|tile| tile.overlaps_disc(center, radius)))
+    }
+
+    /// Distance in radians from `point` to the center of the nearest tile the
+    /// index covers, 0 for indexes of the whole sky.
+    pub fn distance_to(&self, point: &Vector3<f64>) -> f64 {
+        self.coverage().map_or(0.0, |tiles| {
+            tiles
+                .iter()
+                .map(|tile| angular_distance(&tile.center(), point))
+                .fold(f64::INFINITY, f64::min)
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Index {
-    nside: u32,
+    metadata: IndexMetadata,
     quad_index: KdTree<([f64; 4], Quad<IndexStar>)>,
-    position_index: KdTree<([f64; 2], IndexStar)>,
+    position_index: KdTree<([f64; 3], IndexStar)>,
 }
 
 impl Index {
     pub fn new(
-        nside: u32,
+        metadata: IndexMetadata,
         quads: impl Iterator<Item = Quad<IndexStar>>,
         stars: impl Iterator<Item = IndexStar>,
     ) -> Self {
         let quad_points = quads.map(|q| (q.ghash(), q)).collect::<Vec<_>>();
-        let position_points = stars.map(|s| (s.position, s)).collect::<Vec<_>>();
+        let position_points = stars.map(|s| (s.xyz().into(), s)).collect::<Vec<_>>();
 
         Self {
-            nside,
+            metadata,
             quad_index: KdTree::build_by_ordered_float(quad_points),
             position_index: KdTree::build_by_ordered_float(position_points),
         }
     }
 
+    /// Write the index to disk, see [`mapped_index::write`] for the format.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        mapped_index::write(self, path)
+    }
+
+    /// Load an index file fully into memory, checking it for corruption.
+    /// Use [`MappedIndex`] to search an index without loading it.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let mapped = MappedIndex::open(path)?;
+        mapped.verify()?;
+        mapped.to_index()
+    }
+
+    /// Read only the metadata of an index file, without loading the index.
+    pub fn read_metadata(path: impl AsRef<Path>) -> Result<IndexMetadata> {
+        Ok(MappedIndex::open(path)?.metadata().clone())
+    }
+
+    pub fn metadata(&self) -> &IndexMetadata {
+        &self.metadata
+    }
+
     pub fn nside(&self) -> u32 {
-        self.nside
+        self.metadata.nside
     }
 
     pub fn quad_index(&self) -> &KdTree<([f64; 4], Quad<IndexStar>)> {
         &self.quad_index
     }
 
-    pub fn position_index(&self) -> &KdTree<([f64; 2], IndexStar)> {
+    /// Find all index quads whose hash lies within `radius` of `ghash` in code space.
+    pub fn quads_within(&self, ghash: &GHash, radius: f64) -> Vec<&Quad<IndexStar>> {
+        self.quad_index
+            .within_radius(ghash, radius)
+            .into_iter()
+            .map(|(_, quad)| quad)
+            .collect()
+    }
+
+    /// Find index quads that may correspond to `quad`, a quad of image stars
+    /// whose positions are uncertain by `sigma` (in the same units as the
+    /// positions the quad was built from, usually pixels).
+    pub fn matching_quads<Star: Debug>(
+        &self,
+        quad: &Quad<Star>,
+        sigma: f64,
+    ) -> Vec<&Quad<IndexStar>> {
+        let radius = CODE_TOLERANCE_FACTOR * code_tolerance(sigma, quad.scale());
+
+        self.quads_within(&quad.ghash(), radius)
+    }
+
+    /// Stars keyed by their position as a unit vector.
+    pub fn position_index(&self) -> &KdTree<([f64; 3], IndexStar)> {
         &self.position_index
     }
+
+    /// Find all stars within `radius` radians of `center`.
+    pub fn stars_within(&self, center: &Vector3<f64>, radius: f64) -> Vec<&IndexStar> {
+        let query: [f64; 3] = (*center).into();
+
+        self.position_index
+            .within_radius(&query, chord_length(radius))
+            .into_iter()
+            .map(|(_, star)| star)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::util::temp_path;
+
+    use itertools::Itertools;
+
+    use crate::mapped_index::INDEX_FORMAT_VERSION;
+
+    use super::*;
+
+    fn test_index() -> Index {
+        let stars = [
+            (10.0, -89.5),
+            (100.0, -89.2),
+            (250.0, -89.7),
+            (330.0, -89.4),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (ra, dec))| {
+            let motion = Motion {
+                proper_motion: [0.01 * i as f32, -0.02],
+                epoch: 1980.0 + i as f32,
+            };
+            IndexStar::new(
+                StarId::usnob(0, i as u64).unwrap(),
+                [ra, dec],
+                12.0 + i as f32,
+                motion,
+                i as u32,
+            )
+        })
+        .collect::<Vec<_>>();
+
+        let quad =
+            Quad::from_unit_vectors([0, 1, 2, 3].map(|i| (stars[i].xyz(), stars[i].clone())))
+                .unwrap();
+
+        let metadata = IndexMetadata {
+            nside: 8,
+            scale_range: (30.0, 60.0),
+            catalog: "USNO-B1.0".to_string(),
+            band: "R".to_string(),
+            tile: None,
+            merged_tiles: Vec::new(),
+            settings: None,
+        };
+
+        Index::new(metadata, [quad].into_iter(), stars.into_iter())
+    }
+
+    #[test]
+    fn test_sky_tile_parent() {
+        let tile = SkyTile {
+            nside: 4,
+            pixel: 100,
+        };
+
+        assert_eq!(tile.parent(4), Some(tile));
+        assert_eq!(
+            tile.parent(2),
+            Some(SkyTile {
+                nside: 2,
+                pixel: 25
+            })
+        );
+        assert_eq!(tile.parent(1), Some(SkyTile { nside: 1, pixel: 6 }));
+        assert_eq!(tile.parent(8), None);
+        assert!(tile.parent(1).unwrap().contains_cell(4, 100));
+    }
+
+    #[test]
+    fn test_stars_within() {
+        // Close pairs across RA 0/360 and across the pole
+        let stars = [
+            (359.95, 10.0),
+            (0.05, 10.0),
+            (0.0, 89.95),
+            (180.0, 89.95),
+            (90.0, 0.0),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (ra, dec))| {
+            IndexStar::new(
+                StarId::usnob(0, i as u64).unwrap(),
+                [ra, dec],
+                10.0,
+                Motion::default(),
+                0,
+            )
+        })
+        .collect::<Vec<_>>();
+
+        let metadata = test_index().metadata().clone();
+        let index = Index::new(metadata, [].into_iter(), stars.clone().into_iter());
+
+        let designations = |center: (f64, f64), radius: f64| {
+            index
+                .stars_within(&radec_to_xyz(center.0, center.1), radius.to_radians())
+                .into_iter()
+                .map(|s| s.designation())
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            designations((0.0, 10.0), 0.1),
+            ["0000-0000000", "0000-0000001"]
+        );
+        assert_eq!(designations((359.95, 10.0), 0.01), ["0000-0000000"]);
+        assert_eq!(
+            designations((270.0, 90.0), 0.1),
+            ["0000-0000002", "0000-0000003"]
+        );
+        assert_eq!(designations((0.0, 89.95), 0.05), ["0000-0000002"]);
+        assert_eq!(designations((0.0, 0.0), 180.0).len(), stars.len());
+    }
+
+    #[test]
+    fn test_proper_motion() {
+        // 1"/yr north and 2"/yr east over 36 years
+        let motion = Motion {
+            proper_motion: [2.0, 1.0],
+            epoch: 1990.0,
+        };
+        let star = IndexStar::new(StarId::usnob(1, 1).unwrap(), [10.0, 60.0], 15.0, motion, 0);
+
+        let [ra, dec] = star.position_at(1990.0);
+        assert!((ra - 10.0).abs() < 1e-9 && (dec - 60.0).abs() < 1e-9);
+
+        let [ra, dec] = star.position_at(2026.0);
+        assert!((dec - (60.0 + 0.01)).abs() < 1e-4);
+        assert!((ra - (10.0 + 0.02 / 60f64.to_radians().cos())).abs() < 1e-4);
+
+        // Moving across the pole
+        let motion = Motion {
+            proper_motion: [0.0, 10.0],
+            epoch: 2000.0,
+        };
+        let star = IndexStar::new(
+            StarId::usnob(1, 2).unwrap(),
+            [45.0, 90.0 - 5e-3],
+            15.0,
+            motion,
+            0,
+        );
+        let [ra, dec] = star.position_at(2003.6);
+        assert!((dec - (90.0 - 5e-3)).abs() < 1e-6);
+        assert!((ra - 225.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = temp_path("save-load.idx");
+        let index = test_index();
+
+        index.save(&path).unwrap();
+
+        assert_eq!(Index::read_metadata(&path).unwrap(), *index.metadata());
+
+        let loaded = Index::load(&path).unwrap();
+        assert_eq!(loaded.metadata(), index.metadata());
+        assert_eq!(&loaded.quad_index()[..], &index.quad_index()[..]);
+        // Star order within the tree depends on the order they were inserted in
+        let sorted = |index: &Index| {
+            index
+                .position_index()
+                .iter()
+                .cloned()
+                .sorted_by_key(|(_, star)| star.id())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(&loaded), sorted(&index));
+
+        let mapped = MappedIndex::open(&path).unwrap();
+        let center = radec_to_xyz(0.0, -90.0);
+        let found = mapped.stars_within(&center, 0.6f64.to_radians());
+        assert_eq!(found.len(), 3);
+        assert!(found.iter().all(|&i| index
+            .stars_within(&center, 0.6f64.to_radians())
+            .contains(&&mapped.star(i))));
+
+        let (ghash, quad) = &index.quad_index()[0];
+        let found = mapped.quads_within(ghash, 1e-9);
+        assert_eq!(found.len(), 1);
+        assert_eq!(mapped.quad(found[0]).unwrap(), *quad);
+        drop(mapped);
+
+        // Corrupt the payload
+        let mut bytes = fs::read(&path).unwrap();
+        let len = bytes.len();
+        bytes[len - 10] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        assert!(Index::load(&path).is_err());
+
+        // Unsupported version
+        bytes[8..12].copy_from_slice(&(INDEX_FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(Index::read_metadata(&path).is_err());
+
+        fs::write(&path, b"SIMPLE  =").unwrap();
+        assert!(Index::load(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
 }
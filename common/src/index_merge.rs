@@ -0,0 +1,398 @@
+/// Merging index files and splitting them into sky tiles
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use anyhow::{ensure, Result};
+use itertools::Itertools;
+use nalgebra::Vector3;
+
+use crate::{
+    error::AstroError,
+    healpix::{npix, vec2pix_nested},
+    index::{Index, IndexMetadata, SkyTile},
+    mapped_index::MappedIndex,
+};
+
+/// Whether two indexes were built with the same parameters, regardless of
+/// the part of the sky they cover.
+fn same_parameters(a: &IndexMetadata, b: &IndexMetadata) -> bool {
+    let parameters = |metadata: &IndexMetadata| IndexMetadata {
+        tile: None,
+        merged_tiles: Vec::new(),
+        ..metadata.clone()
+    };
+
+    parameters(a) == parameters(b)
+}
+
+/// The smallest tile containing all of `tiles`, None if only the whole sky
+/// covers them.
+fn enclosing_tile(tiles: &[SkyTile]) -> Option<SkyTile> {
+    let mut nside = tiles.iter().map(|tile| tile.nside).min()?;
+
+    loop {
+        if let Ok(Some(parent)) = tiles
+            .iter()
+            .map(|tile| tile.parent(nside))
+            .all_equal_value()
+        {
+            return Some(parent);
+        }
+        if nside == 1 {
+            return None;
+        }
+        nside /= 2;
+    }
+}
+
+/// The part of the sky covered by indexes covering each of `coverages` (see
+/// [`IndexMetadata::coverage`]), as the `tile` and `merged_tiles` of the
+/// merged index.
+fn merged_coverage(coverages: &[Option<Vec<SkyTile>>]) -> (Option<SkyTile>, Vec<SkyTile>) {
+    let Some(tiles) = coverages.iter().cloned().collect::<Option<Vec<_>>>() else {
+        return (None, Vec::new());
+    };
+    let tiles = tiles.into_iter().flatten().unique().collect::<Vec<_>>();
+
+    // Tiles inside other tiles add nothing
+    let tiles = tiles
+        .iter()
+        .filter(|tile| {
+            !tiles
+                .iter()
+                .any(|other| other != *tile && tile.parent(other.nside) == Some(*other))
+        })
+        .copied()
+        .sorted_by_key(|tile| (tile.nside, tile.pixel))
+        .collect::<Vec<_>>();
+
+    let enclosing = enclosing_tile(&tiles);
+
+    // The tiles are disjoint now, so they make up the enclosing tile or the
+    // whole sky if their areas add up to it
+    let Some(finest) = tiles.iter().map(|tile| tile.nside).max() else {
+        return (None, Vec::new());
+    };
+    let area = tiles
+        .iter()
+        .map(|tile| npix(finest) / npix(tile.nside))
+        .sum::<u64>();
+    let enclosing_area = enclosing.map_or(npix(finest), |tile| npix(finest) / npix(tile.nside));
+
+    if area == enclosing_area {
+        (enclosing, Vec::new())
+    } else {
+        (enclosing, tiles)
+    }
+}
+
+/// Merge indexes built with the same parameters, e.g. for neighbouring tiles
+/// or overlapping regions, into one. Stars and quads contained in several of
+/// them are only kept once. Tiles that don't make up a whole tile are kept in
+/// the metadata, so the merged index doesn't claim more of the sky than its
+/// sources covered. The merged index is built in memory, so this is meant for
+/// a few tiles rather than a whole sky's worth of them.
+pub fn merge(indexes: &[MappedIndex]) -> Result<Index> {
+    let first = indexes
+        .first()
+        .ok_or(AstroError::new("No indexes to merge"))?;
+
+    for index in indexes {
+        ensure!(
+            same_parameters(index.metadata(), first.metadata()),
+            AstroError::new(&format!(
+                "{} was built with different parameters than {}",
+                index.path().display(),
+                first.path().display()
+            ))
+        );
+    }
+
+    let mut star_ids = HashSet::new();
+    let mut stars = Vec::new();
+    let mut quad_ids = HashSet::new();
+    let mut quads = Vec::new();
+
+    for index in indexes {
+        for star in 0..index.num_stars() {
+            if star_ids.insert(index.star_id(star)) {
+                stars.push(index.star(star));
+            }
+        }
+
+        for quad in 0..index.num_quads() {
+            let members = index.quad_members(quad)?;
+            if quad_ids.insert(members.map(|star| index.star_id(star))) {
+                quads.push(index.quad(quad)?);
+            }
+        }
+    }
+
+    let coverages = indexes
+        .iter()
+        .map(|index| index.metadata().coverage())
+        .collect::<Vec<_>>();
+    let (tile, merged_tiles) = merged_coverage(&coverages);
+    let metadata = IndexMetadata {
+        tile,
+        merged_tiles,
+        ..first.metadata().clone()
+    };
+
+    Ok(Index::new(metadata, quads.into_iter(), stars.into_iter()))
+}
+
+/// Split an index into the tiles of resolution `nside` it covers.
+///
+/// Each quad goes to the tile containing the midpoint of its backbone AB, as
+/// when building tiled indexes. A tile keeps the stars inside it and those of
+/// its quads, so stars near tile boundaries may end up in several tiles.
+/// Tiles without quads are left out.
+pub fn split(index: &MappedIndex, nside: u32) -> Result<Vec<Index>> {
+    ensure!(
+        nside.is_power_of_two() && index.metadata().tile.is_none_or(|tile| nside >= tile.nside),
+        AstroError::new(&format!(
+            "Can't split {} into tiles at nside {}",
+            index.path().display(),
+            nside
+        ))
+    );
+
+    let xyz = |star: usize| Vector3::from(index.star_vectors()[star]);
+
+    let mut tiles: BTreeMap<u64, (BTreeSet<usize>, Vec<usize>)> = BTreeMap::new();
+
+    for quad in 0..index.num_quads() {
+        let members = index.quad_members(quad)?;
+        let midpoint = (xyz(members[0]) + xyz(members[1])).normalize();
+        let (stars, quads) = tiles.entry(vec2pix_nested(nside, &midpoint)).or_default();
+
+        stars.extend(members);
+        quads.push(quad);
+    }
+
+    for star in 0..index.num_stars() {
+        if let Some((stars, _)) = tiles.get_mut(&vec2pix_nested(nside, &xyz(star))) {
+            stars.insert(star);
+        }
+    }
+
+    tiles
+        .into_iter()
+        .map(|(pixel, (stars, quads))| {
+            let metadata = IndexMetadata {
+                tile: Some(SkyTile { nside, pixel }),
+                merged_tiles: Vec::new(),
+                ..index.metadata().clone()
+            };
+
+            let quads = quads
+                .into_iter()
+                .map(|quad| index.quad(quad))
+                .collect::<Result<Vec<_>>>()?;
+
+            Ok(Index::new(
+                metadata,
+                quads.into_iter(),
+                stars.into_iter().map(|star| index.star(star)),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::util::temp_path;
+
+    use crate::{
+        index::{IndexStar, Motion},
+        quad::Quad,
+        star_id::StarId,
+    };
+
+    use super::*;
+
+    /// Stars and quads straddling the boundary between base pixels 0 and 4
+    fn test_index(band: &str) -> Index {
+        let stars = (0..12)
+            .map(|i| {
+                let position = [18.75 + (i % 4) as f64 * 2.5, 17.5 + (i / 4) as f64 * 2.0];
+                IndexStar::new(
+                    StarId::usnob(0, i).unwrap(),
+                    position,
+                    10.0,
+                    Motion::default(),
+                    0,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let quads = stars
+            .iter()
+            .tuple_combinations()
+            .filter_map(|(a, b, c, d)| {
+                Quad::from_unit_vectors([a, b, c, d].map(|s| (s.xyz(), s.clone())))
+            })
+            .collect::<Vec<_>>();
+
+        let metadata = IndexMetadata {
+            nside: 8,
+            scale_range: (60.0, 600.0),
+            catalog: "USNO-B1.0".to_string(),
+            band: band.to_string(),
+            tile: None,
+            merged_tiles: Vec::new(),
+            settings: None,
+        };
+
+        Index::new(metadata, quads.into_iter(), stars.into_iter())
+    }
+
+    #[test]
+    fn test_merged_coverage() {
+        let tile = |nside, pixel| SkyTile { nside, pixel };
+
+        // All children of a tile make up the tile
+        let children = (16..20)
+            .map(|pixel| Some(vec![tile(2, pixel)]))
+            .collect::<Vec<_>>();
+        assert_eq!(merged_coverage(&children), (Some(tile(1, 4)), vec![]));
+
+        // Some of them don't
+        assert_eq!(
+            merged_coverage(&children[1..]),
+            (
+                Some(tile(1, 4)),
+                (17..20).map(|pixel| tile(2, pixel)).collect()
+            )
+        );
+
+        // Tiles inside other tiles are dropped
+        let nested = [Some(vec![tile(1, 4)]), Some(vec![tile(4, 70)])];
+        assert_eq!(merged_coverage(&nested), (Some(tile(1, 4)), vec![]));
+
+        // Distant tiles are only enclosed by the whole sky
+        let distant = [Some(vec![tile(4, 0)]), Some(vec![tile(4, 191)])];
+        assert_eq!(
+            merged_coverage(&distant),
+            (None, vec![tile(4, 0), tile(4, 191)])
+        );
+
+        // Base pixels making up the whole sky, or an all-sky index
+        let base = (0..12)
+            .map(|pixel| Some(vec![tile(1, pixel)]))
+            .collect::<Vec<_>>();
+        assert_eq!(merged_coverage(&base), (None, vec![]));
+        assert_eq!(
+            merged_coverage(&[None, Some(vec![tile(1, 0)])]),
+            (None, vec![])
+        );
+    }
+
+    fn sorted_ids(index: &MappedIndex) -> Vec<StarId> {
+        (0..index.num_stars())
+            .map(|star| index.star_id(star))
+            .sorted()
+            .collect()
+    }
+
+    #[test]
+    fn test_split_merge() {
+        let dir = temp_path("index-merge");
+        fs::create_dir_all(&dir).unwrap();
+
+        test_index("R").save(dir.join("full.idx")).unwrap();
+        let full = MappedIndex::open(dir.join("full.idx")).unwrap();
+
+        let tiles = split(&full, 1).unwrap();
+        assert_eq!(
+            tiles
+                .iter()
+                .map(|tile| tile.metadata().tile.unwrap().pixel)
+                .collect::<Vec<_>>(),
+            [0, 4]
+        );
+        assert_eq!(
+            tiles
+                .iter()
+                .map(|tile| tile.quad_index().len())
+                .sum::<usize>(),
+            full.num_quads()
+        );
+
+        let mut paths = Vec::new();
+        for tile in &tiles {
+            let sky_tile = tile.metadata().tile.unwrap();
+            for (_, quad) in tile.quad_index().iter() {
+                let [a, b, _, _] = quad.get_stars();
+                assert!(sky_tile.contains(&(a.xyz() + b.xyz()).normalize()));
+            }
+
+            let path = dir.join(format!("tile-{}.idx", sky_tile.pixel));
+            tile.save(&path).unwrap();
+            paths.push(path);
+        }
+
+        let tiles = paths
+            .iter()
+            .map(|path| MappedIndex::open(path).unwrap())
+            .collect::<Vec<_>>();
+
+        // Stars near the boundary are in both tiles
+        assert!(tiles[0].num_stars() + tiles[1].num_stars() > full.num_stars());
+
+        // Base pixels 0 and 4 aren't enclosed by any tile, but the merged
+        // index only covers those two
+        let merged = merge(&tiles).unwrap();
+        assert_eq!(merged.metadata().tile, None);
+        assert_eq!(
+            merged.metadata().merged_tiles,
+            [0, 4].map(|pixel| SkyTile { nside: 1, pixel })
+        );
+        assert!(same_parameters(merged.metadata(), full.metadata()));
+        assert!(!merged
+            .metadata()
+            .covers(&-tiles[0].metadata().tile.unwrap().center()));
+        merged.save(dir.join("merged.idx")).unwrap();
+        let merged = MappedIndex::open(dir.join("merged.idx")).unwrap();
+        assert_eq!(sorted_ids(&merged), sorted_ids(&full));
+        assert_eq!(merged.num_quads(), full.num_quads());
+
+        // Merging a tile with itself changes nothing
+        let merged = merge(&[
+            MappedIndex::open(&paths[0]).unwrap(),
+            MappedIndex::open(&paths[0]).unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(merged.metadata(), tiles[0].metadata());
+        assert_eq!(merged.position_index().len(), tiles[0].num_stars());
+        assert_eq!(merged.quad_index().len(), tiles[0].num_quads());
+
+        test_index("B").save(dir.join("other.idx")).unwrap();
+        assert!(merge(&[full, MappedIndex::open(dir.join("other.idx")).unwrap()]).is_err());
+        assert!(merge(&[]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_enclosing_tile() {
+        let tile = |nside, pixel| SkyTile { nside, pixel };
+
+        assert_eq!(
+            enclosing_tile(&[tile(4, 80), tile(4, 81)]),
+            Some(tile(2, 20))
+        );
+        assert_eq!(
+            enclosing_tile(&[tile(4, 80), tile(2, 20)]),
+            Some(tile(2, 20))
+        );
+        assert_eq!(
+            enclosing_tile(&[tile(4, 80), tile(4, 95)]),
+            Some(tile(1, 5))
+        );
+        assert_eq!(enclosing_tile(&[tile(4, 80), tile(4, 96)]), None);
+        assert_eq!(enclosing_tile(&[]), None);
+    }
+}
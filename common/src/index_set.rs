@@ -0,0 +1,263 @@
+/// Series of indexes covering different quad scales and parts of the sky
+use std::path::Path;
+
+use anyhow::{ensure, Result};
+
+use crate::{error::AstroError, mapped_index::MappedIndex, sphere::radec_to_xyz};
+
+/// File extension index files are recognized by
+pub const INDEX_EXTENSION: &str = "idx";
+
+/// Quads much smaller than the image rarely have all four stars detected,
+/// so only quads of at least this fraction of the image's shorter side are
+/// worth looking for. The largest usable quads span the image's diagonal.
+const MIN_QUAD_FRACTION: f64 = 0.1;
+
+/// Approximate location of an image on the sky
+#[derive(Debug, Clone, Copy)]
+pub struct PositionHint {
+    /// Center (RA, Dec) in degrees
+    pub ra: f64,
+    pub dec: f64,
+    /// Radius in degrees around the center that contains the whole image
+    pub radius: f64,
+}
+
+/// What is known about an image before solving it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SolveHint {
+    /// Range of possible pixel scales in arcseconds per pixel
+    pub pixel_scale: Option<(f64, f64)>,
+    pub position: Option<PositionHint>,
+}
+
+/// A set of indexes, typically a directory of them built for different
+/// scale bands and sky tiles
+pub struct IndexSet {
+    indexes: Vec<MappedIndex>,
+}
+
+impl IndexSet {
+    pub fn new(indexes: Vec<MappedIndex>) -> Self {
+        Self { indexes }
+    }
+
+    /// Map all `.idx` files in `dir`.
+    pub fn open_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut paths = Vec::new();
+
+        for entry in dir.read_dir()? {
+            let path = entry?.path();
+            if path.is_file() && path.extension() == Some(INDEX_EXTENSION.as_ref()) {
+                paths.push(path);
+            }
+        }
+
+        ensure!(
+            !paths.is_empty(),
+            AstroError::new(&format!("No index files in {}", dir.display()))
+        );
+
+        paths.sort();
+
+        Ok(Self::new(
+            paths
+                .iter()
+                .map(MappedIndex::open)
+                .collect::<Result<Vec<_>>>()?,
+        ))
+    }
+
+    pub fn indexes(&self) -> &[MappedIndex] {
+        &self.indexes
+    }
+
+    /// Range of quad scales in arcminutes that can be found in an image of
+    /// `width` x `height` pixels, given its range of pixel scales.
+    fn usable_scales(width: usize, height: usize, pixel_scale: (f64, f64)) -> (f64, f64) {
+        let shorter = width.min(height) as f64;
+        let diagonal = (width as f64).hypot(height as f64);
+
+        (
+            MIN_QUAD_FRACTION * shorter * pixel_scale.0 / 60.0,
+            diagonal * pixel_scale.1 / 60.0,
+        )
+    }
+
+    /// Indexes worth searching for an image of `width` x `height` pixels, in
+    /// the order they should be searched.
+    ///
+    /// Indexes whose scale band can't occur in the image or whose tile lies
+    /// outside the hinted position are left out. Indexes of larger quads come
+    /// first, since they are smaller and their quads are more robust to
+    /// missing stars; among equal scales, tiles closer to the hinted position
+    /// come first.
+    pub fn select(&self, width: usize, height: usize, hint: &SolveHint) -> Vec<&MappedIndex> {
+        let scales = hint
+            .pixel_scale
+            .map(|pixel_scale| Self::usable_scales(width, height, pixel_scale));
+        let position = hint
+            .position
+            .map(|p| (radec_to_xyz(p.ra, p.dec), p.radius.to_radians()));
+
+        // Distance of each index's tiles from the hinted position, 0 for all-sky indexes
+        let distance = |index: &MappedIndex| match position {
+            Some((center, _)) => index.metadata().distance_to(&center),
+            None => 0.0,
+        };
+
+        let mut selected = self
+            .indexes
+            .iter()
+            .filter(|index| {
+                let (min, max) = index.metadata().scale_range;
+                scales.is_none_or(|scales| min <= scales.1 && max >= scales.0)
+            })
+            .filter(|index| {
+                position
+                    .is_none_or(|(center, radius)| index.metadata().overlaps_disc(&center, radius))
+            })
+            .collect::<Vec<_>>();
+
+        selected.sort_by(|a, b| {
+            b.metadata()
+                .scale_range
+                .1
+                .total_cmp(&a.metadata().scale_range.1)
+                .then(distance(a).total_cmp(&distance(b)))
+                .then_with(|| a.path().cmp(b.path()))
+        });
+
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::util::temp_path;
+
+    use crate::{
+        index::{Index, IndexMetadata, IndexStar, Motion, SkyTile},
+        star_id::StarId,
+    };
+
+    use super::*;
+
+    fn write_index(
+        dir: &Path,
+        name: &str,
+        scale_range: (f64, f64),
+        tile: Option<SkyTile>,
+        merged_tiles: Vec<SkyTile>,
+    ) {
+        let metadata = IndexMetadata {
+            nside: 8,
+            scale_range,
+            catalog: "USNO-B1.0".to_string(),
+            band: "R".to_string(),
+            tile,
+            merged_tiles,
+            settings: None,
+        };
+        let stars = [IndexStar::new(
+            StarId::usnob(0, 1).unwrap(),
+            [0.0, 0.0],
+            10.0,
+            Motion::default(),
+            0,
+        )];
+
+        Index::new(metadata, [].into_iter(), stars.into_iter())
+            .save(dir.join(name))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_select() {
+        let dir = temp_path("index-set");
+        fs::create_dir_all(&dir).unwrap();
+
+        write_index(&dir, "coarse.idx", (60.0, 120.0), None, vec![]);
+        write_index(&dir, "fine.idx", (2.0, 4.0), None, vec![]);
+        // Base pixels 4 and 6 are centered on the equator at RA 0 and 180,
+        // 0 and 8 at RA 45 and Dec 41.8 and -41.8
+        let tile = |pixel| SkyTile { nside: 1, pixel };
+        write_index(&dir, "tile-4.idx", (10.0, 20.0), Some(tile(4)), vec![]);
+        write_index(&dir, "tile-6.idx", (10.0, 20.0), Some(tile(6)), vec![]);
+        write_index(&dir, "merged.idx", (2.0, 4.0), None, vec![tile(0), tile(8)]);
+        fs::write(dir.join("notes.txt"), "not an index").unwrap();
+
+        let set = IndexSet::open_dir(&dir).unwrap();
+        assert_eq!(set.indexes().len(), 5);
+
+        let names = |selected: Vec<&MappedIndex>| {
+            selected
+                .into_iter()
+                .map(|index| {
+                    index
+                        .path()
+                        .file_name()
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let no_hint = SolveHint::default();
+        assert_eq!(
+            names(set.select(1000, 1000, &no_hint)),
+            [
+                "coarse.idx",
+                "tile-4.idx",
+                "tile-6.idx",
+                "fine.idx",
+                "merged.idx"
+            ]
+        );
+
+        // 1000 px at 1"/px is about 17' across
+        let scale_hint = SolveHint {
+            pixel_scale: Some((0.9, 1.1)),
+            position: None,
+        };
+        assert_eq!(
+            names(set.select(1000, 1000, &scale_hint)),
+            ["tile-4.idx", "tile-6.idx", "fine.idx", "merged.idx"]
+        );
+
+        let position_hint = SolveHint {
+            pixel_scale: Some((0.9, 1.1)),
+            position: Some(PositionHint {
+                ra: 170.0,
+                dec: 10.0,
+                radius: 5.0,
+            }),
+        };
+        assert_eq!(
+            names(set.select(1000, 1000, &position_hint)),
+            ["tile-6.idx", "fine.idx"]
+        );
+
+        // Merged indexes only cover the tiles they were merged from
+        let position_hint = SolveHint {
+            pixel_scale: Some((0.9, 1.1)),
+            position: Some(PositionHint {
+                ra: 45.0,
+                dec: -40.0,
+                radius: 5.0,
+            }),
+        };
+        assert_eq!(
+            names(set.select(1000, 1000, &position_hint)),
+            ["fine.idx", "merged.idx"]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(IndexSet::open_dir(&dir).is_err());
+    }
+}
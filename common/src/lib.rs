@@ -1,6 +1,14 @@
 pub mod error;
 pub mod fits_bintable;
+pub mod flat_kdtree;
+pub mod healpix;
 pub mod index;
+pub mod index_merge;
+pub mod index_set;
+pub mod mapped_index;
 pub mod quad;
+pub mod quad_builder;
+pub mod sphere;
+pub mod star_id;
 pub mod usnob;
 pub mod util;
@@ -0,0 +1,554 @@
+/// Memory-mapped index files
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    fs::File,
+    io::{BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Result};
+use bytemuck::Pod;
+use memmap2::Mmap;
+use nalgebra::Vector3;
+
+use crate::{
+    error::AstroError,
+    flat_kdtree,
+    index::{Index, IndexMetadata, IndexStar, Motion, CODE_TOLERANCE_FACTOR},
+    quad::{code_tolerance, GHash, Quad},
+    sphere::chord_length,
+    star_id::StarId,
+};
+
+pub const INDEX_MAGIC: &[u8; 8] = b"ASTRMIDX";
+pub const INDEX_FORMAT_VERSION: u32 = 6;
+
+const ALIGNMENT: usize = 8;
+
+fn padding(position: usize) -> usize {
+    (ALIGNMENT - position % ALIGNMENT) % ALIGNMENT
+}
+
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+    position: usize,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.hasher.update(bytes);
+        self.inner.write_all(bytes)?;
+        self.position += bytes.len();
+        Ok(())
+    }
+
+    fn write_slice<T: Pod>(&mut self, items: &[T]) -> Result<()> {
+        self.write(bytemuck::cast_slice(items))?;
+        self.write(&[0; ALIGNMENT][..padding(self.position)])
+    }
+}
+
+/// Write an index to disk in a layout that can be searched straight from a
+/// memory map. All numbers are little endian and every array starts at a
+/// multiple of 8 bytes:
+///
+/// | Content                                                  | Type                  |
+/// |----------------------------------------------------------|-----------------------|
+/// | Magic number `ASTRMIDX`                                  | `[u8; 8]`             |
+/// | Format version                                           | `u32`                 |
+/// | Length of the metadata                                   | `u32`                 |
+/// | Metadata as JSON                                         | `[u8]`                |
+/// | Number of stars, number of quads                         | `[u64; 2]`            |
+/// | Star positions as unit vectors in kd-tree order          | `[[f64; 3]; n_stars]` |
+/// | Star positions (RA, Dec) in the same order               | `[[f64; 2]; n_stars]` |
+/// | Catalog IDs of the stars, see [`StarId`]                 | `[u64; n_stars]`      |
+/// | Magnitude of each star in the index's band               | `[f32; n_stars]`      |
+/// | Proper motion in RA and Dec and epoch of each star       | `[[f32; 3]; n_stars]` |
+/// | Uniformization sweep of each star                        | `[u32; n_stars]`      |
+/// | Quad codes in kd-tree order                              | `[[f64; 4]; n_quads]` |
+/// | Indices of each quad's stars A, B, C and D               | `[[u32; 4]; n_quads]` |
+/// | Quad scales                                              | `[f64; n_quads]`      |
+/// | CRC32 of everything after the magic number               | `u32`                 |
+pub fn write(index: &Index, path: impl AsRef<Path>) -> Result<()> {
+    ensure!(
+        cfg!(target_endian = "little"),
+        AstroError::new("Index files can only be written on little endian machines")
+    );
+
+    let stars = index.position_index();
+    let quads = index.quad_index();
+
+    let star_order = flat_kdtree::kd_order(&stars.iter().map(|(p, _)| *p).collect::<Vec<_>>());
+    let quad_order = flat_kdtree::kd_order(&quads.iter().map(|(c, _)| *c).collect::<Vec<_>>());
+
+    // Position of each star in the file. Quads refer to their stars by ID,
+    // so IDs must be unique for quads to find the right ones
+    let mut star_indices = HashMap::with_capacity(stars.len());
+    for (index, &i) in star_order.iter().enumerate() {
+        let id = stars[i].1.id();
+        ensure!(
+            star_indices.insert(id, index as u32).is_none(),
+            AstroError::new(&format!("Star {} is in the index more than once", id))
+        );
+    }
+
+    let quad_stars = quad_order
+        .iter()
+        .map(|&i| {
+            let mut ids = [0u32; 4];
+            for (id, star) in ids.iter_mut().zip(quads[i].1.get_stars()) {
+                *id = *star_indices
+                    .get(&star.id())
+                    .ok_or(AstroError::new(&format!(
+                        "Quad star {} is not in the index",
+                        star.id()
+                    )))?;
+            }
+            Ok(ids)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let metadata = serde_json::to_vec(index.metadata())?;
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(INDEX_MAGIC)?;
+
+    let mut writer = ChecksumWriter {
+        inner: file,
+        hasher: crc32fast::Hasher::new(),
+        position: INDEX_MAGIC.len(),
+    };
+
+    writer.write(&INDEX_FORMAT_VERSION.to_le_bytes())?;
+    writer.write(&(metadata.len() as u32).to_le_bytes())?;
+    writer.write_slice(&metadata)?;
+    writer.write_slice(&[stars.len() as u64, quads.len() as u64])?;
+    writer.write_slice(&star_order.iter().map(|&i| stars[i].0).collect::<Vec<_>>())?;
+    writer.write_slice(
+        &star_order
+            .iter()
+            .map(|&i| stars[i].1.position())
+            .collect::<Vec<_>>(),
+    )?;
+    writer.write_slice(
+        &star_order
+            .iter()
+            .map(|&i| stars[i].1.id().raw())
+            .collect::<Vec<_>>(),
+    )?;
+    writer.write_slice(
+        &star_order
+            .iter()
+            .map(|&i| stars[i].1.mag())
+            .collect::<Vec<_>>(),
+    )?;
+    writer.write_slice(
+        &star_order
+            .iter()
+            .map(|&i| {
+                let Motion {
+                    proper_motion: [pm_ra, pm_dec],
+                    epoch,
+                } = stars[i].1.motion();
+                [pm_ra, pm_dec, epoch]
+            })
+            .collect::<Vec<_>>(),
+    )?;
+    writer.write_slice(
+        &star_order
+            .iter()
+            .map(|&i| stars[i].1.sweep())
+            .collect::<Vec<_>>(),
+    )?;
+    writer.write_slice(&quad_order.iter().map(|&i| quads[i].0).collect::<Vec<_>>())?;
+    writer.write_slice(&quad_stars)?;
+    writer.write_slice(
+        &quad_order
+            .iter()
+            .map(|&i| quads[i].1.scale())
+            .collect::<Vec<_>>(),
+    )?;
+
+    let checksum = writer.hasher.finalize();
+    let mut file = writer.inner;
+    file.write_all(&checksum.to_le_bytes())?;
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Read the header of an index file, returning the metadata and
+/// the offset at which the arrays start.
+fn read_header(bytes: &[u8], path: &Path) -> Result<(IndexMetadata, usize)> {
+    let error = |message: &str| AstroError::new(&format!("{}: {}", path.display(), message));
+
+    ensure!(
+        bytes.len() >= 16 && &bytes[..8] == INDEX_MAGIC,
+        error("not an index file")
+    );
+
+    let version = u32::from_le_bytes(bytes[8..12].try_into()?);
+    ensure!(
+        version == INDEX_FORMAT_VERSION,
+        error(&format!(
+            "unsupported index format version {} (expected {})",
+            version, INDEX_FORMAT_VERSION
+        ))
+    );
+
+    let metadata_len = u32::from_le_bytes(bytes[12..16].try_into()?) as usize;
+    let metadata = bytes
+        .get(16..16 + metadata_len)
+        .ok_or(error("truncated header"))?;
+    let metadata = serde_json::from_slice(metadata)?;
+
+    let end = 16 + metadata_len;
+
+    Ok((metadata, end + padding(end)))
+}
+
+/// An index file mapped into memory, searchable without deserializing it.
+///
+/// Stars and quads are referred to by their position in the file. Mapping a
+/// file is cheap: only the pages a search touches are ever read from disk.
+/// The stars a quad refers to are checked when the quad is read, see
+/// [`Self::quad_members`], or all at once by [`Self::verify`].
+pub struct MappedIndex {
+    path: PathBuf,
+    metadata: IndexMetadata,
+    mmap: Mmap,
+    star_vectors: Range<usize>,
+    star_positions: Range<usize>,
+    star_ids: Range<usize>,
+    star_mags: Range<usize>,
+    star_motions: Range<usize>,
+    star_sweeps: Range<usize>,
+    quad_codes: Range<usize>,
+    quad_stars: Range<usize>,
+    quad_scales: Range<usize>,
+}
+
+impl MappedIndex {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        ensure!(
+            cfg!(target_endian = "little"),
+            AstroError::new("Index files can only be mapped on little endian machines")
+        );
+
+        let path = path.as_ref();
+        let file = File::open(path)?;
+
+        // SAFETY: index files are written once and not modified afterwards.
+        // Should that happen anyway, we may read garbage, but the slices we
+        // hand out never outlive the map.
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let (metadata, mut offset) = read_header(&mmap, path)?;
+
+        let counts: &[u64] = bytemuck::try_cast_slice(
+            mmap.get(offset..offset + 16)
+                .ok_or(AstroError::new(&format!("{}: truncated", path.display())))?,
+        )
+        .map_err(|e| AstroError::new(&format!("{}: {}", path.display(), e)))?;
+        let too_large = || {
+            AstroError::new(&format!(
+                "{}: {} stars and {} quads don't fit in the file",
+                path.display(),
+                counts[0],
+                counts[1]
+            ))
+        };
+        let [n_stars, n_quads] = [0, 1].map(|i| usize::try_from(counts[i]).unwrap_or(usize::MAX));
+        offset += 16;
+
+        let mut section = |count: usize, size: usize| -> Result<Range<usize>> {
+            let len = count.checked_mul(size).ok_or_else(too_large)?;
+            let end = offset.checked_add(len).ok_or_else(too_large)?;
+            let range = offset..end;
+            offset = end.checked_add(padding(len)).ok_or_else(too_large)?;
+            Ok(range)
+        };
+
+        let star_vectors = section(n_stars, 24)?;
+        let star_positions = section(n_stars, 16)?;
+        let star_ids = section(n_stars, 8)?;
+        let star_mags = section(n_stars, 4)?;
+        let star_motions = section(n_stars, 12)?;
+        let star_sweeps = section(n_stars, 4)?;
+        let quad_codes = section(n_quads, 32)?;
+        let quad_stars = section(n_quads, 16)?;
+        let quad_scales = section(n_quads, 8)?;
+
+        ensure!(
+            offset.checked_add(4) == Some(mmap.len()),
+            AstroError::new(&format!(
+                "{}: expected {} bytes, file has {}",
+                path.display(),
+                offset.saturating_add(4),
+                mmap.len()
+            ))
+        );
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            metadata,
+            mmap,
+            star_vectors,
+            star_positions,
+            star_ids,
+            star_mags,
+            star_motions,
+            star_sweeps,
+            quad_codes,
+            quad_stars,
+            quad_scales,
+        })
+    }
+
+    /// Check the file against its checksum, and that all quads refer to
+    /// stars in the file. This reads the whole file.
+    pub fn verify(&self) -> Result<()> {
+        let (body, checksum) = self.mmap[INDEX_MAGIC.len()..].split_at(self.mmap.len() - 12);
+
+        ensure!(
+            crc32fast::hash(body) == u32::from_le_bytes(checksum.try_into()?),
+            AstroError::new(&format!("{}: checksum mismatch", self.path.display()))
+        );
+
+        for quad in 0..self.num_quads() {
+            self.quad_members(quad)?;
+        }
+
+        Ok(())
+    }
+
+    fn slice<T: Pod>(&self, range: &Range<usize>) -> &[T] {
+        // Sections are aligned relative to the start of the map, which is
+        // page aligned, and their lengths were checked on open
+        bytemuck::cast_slice(&self.mmap[range.clone()])
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn metadata(&self) -> &IndexMetadata {
+        &self.metadata
+    }
+
+    pub fn num_stars(&self) -> usize {
+        self.star_positions.len() / 16
+    }
+
+    pub fn num_quads(&self) -> usize {
+        self.quad_codes.len() / 32
+    }
+
+    /// Positions of all stars as unit vectors, in kd-tree order.
+    pub fn star_vectors(&self) -> &[[f64; 3]] {
+        self.slice(&self.star_vectors)
+    }
+
+    /// (RA, Dec) of all stars in degrees, in the same order as [`Self::star_vectors`].
+    pub fn star_positions(&self) -> &[[f64; 2]] {
+        self.slice(&self.star_positions)
+    }
+
+    /// Indices of all stars within `radius` radians of `center`.
+    pub fn stars_within(&self, center: &Vector3<f64>, radius: f64) -> Vec<usize> {
+        flat_kdtree::within_radius(self.star_vectors(), &(*center).into(), chord_length(radius))
+    }
+
+    /// Catalog IDs of all stars, see [`StarId::raw`].
+    pub fn star_ids(&self) -> &[u64] {
+        self.slice(&self.star_ids)
+    }
+
+    pub fn star_id(&self, star: usize) -> StarId {
+        StarId::from_raw(self.star_ids()[star])
+    }
+
+    /// Magnitudes of all stars, see [`IndexStar::mag`].
+    pub fn star_mags(&self) -> &[f32] {
+        self.slice(&self.star_mags)
+    }
+
+    /// Proper motion in RA and Dec in arcseconds per year and epoch of all
+    /// stars, see [`Motion`].
+    pub fn star_motions(&self) -> &[[f32; 3]] {
+        self.slice(&self.star_motions)
+    }
+
+    pub fn star_motion(&self, star: usize) -> Motion {
+        let [pm_ra, pm_dec, epoch] = self.star_motions()[star];
+
+        Motion {
+            proper_motion: [pm_ra, pm_dec],
+            epoch,
+        }
+    }
+
+    /// Uniformization sweep of all stars, see [`IndexStar::sweep`].
+    pub fn star_sweeps(&self) -> &[u32] {
+        self.slice(&self.star_sweeps)
+    }
+
+    pub fn star(&self, star: usize) -> IndexStar {
+        IndexStar::new(
+            self.star_id(star),
+            self.star_positions()[star],
+            self.star_mags()[star],
+            self.star_motion(star),
+            self.star_sweeps()[star],
+        )
+    }
+
+    /// Geometric hashes of all quads, in kd-tree order.
+    pub fn quad_codes(&self) -> &[GHash] {
+        self.slice(&self.quad_codes)
+    }
+
+    /// Indices of the stars A, B, C and D of each quad, as stored. They
+    /// aren't checked, see [`Self::quad_members`].
+    pub fn quad_stars(&self) -> &[[u32; 4]] {
+        self.slice(&self.quad_stars)
+    }
+
+    /// Scale of each quad in radians, see [`Quad::scale`].
+    pub fn quad_scales(&self) -> &[f64] {
+        self.slice(&self.quad_scales)
+    }
+
+    /// Indices of all quads whose hash lies within `radius` of `ghash` in code space.
+    pub fn quads_within(&self, ghash: &GHash, radius: f64) -> Vec<usize> {
+        flat_kdtree::within_radius(self.quad_codes(), ghash, radius)
+    }
+
+    /// Indices of quads that may correspond to `quad`, see [`Index::matching_quads`].
+    pub fn matching_quads<Star: Debug>(&self, quad: &Quad<Star>, sigma: f64) -> Vec<usize> {
+        let radius = CODE_TOLERANCE_FACTOR * code_tolerance(sigma, quad.scale());
+
+        self.quads_within(&quad.ghash(), radius)
+    }
+
+    /// Indices of the stars A, B, C and D of a quad, failing if the file is
+    /// corrupt and they lie beyond its stars.
+    pub fn quad_members(&self, quad: usize) -> Result<[usize; 4]> {
+        let members = self.quad_stars()[quad].map(|star| star as usize);
+
+        ensure!(
+            members.iter().all(|&star| star < self.num_stars()),
+            AstroError::new(&format!(
+                "{}: quad {} refers to stars beyond the {} in the file",
+                self.path.display(),
+                quad,
+                self.num_stars()
+            ))
+        );
+
+        Ok(members)
+    }
+
+    pub fn quad(&self, quad: usize) -> Result<Quad<IndexStar>> {
+        Ok(Quad::from_parts(
+            self.quad_members(quad)?.map(|star| self.star(star)),
+            self.quad_codes()[quad],
+            self.quad_scales()[quad],
+        ))
+    }
+
+    /// Load the whole index into memory.
+    pub fn to_index(&self) -> Result<Index> {
+        let stars = (0..self.num_stars()).map(|i| self.star(i));
+        let quads = (0..self.num_quads())
+            .map(|i| self.quad(i))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Index::new(self.metadata.clone(), quads.into_iter(), stars))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::util::temp_path;
+
+    use crate::{
+        index::{IndexMetadata, IndexStar, Motion},
+        star_id::StarId,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_corrupt_counts_and_stars() {
+        let path = temp_path("corrupt.idx");
+
+        let stars = (0..4)
+            .map(|i| {
+                let position = [10.0 + i as f64 * 0.1, 20.0 + (i % 2) as f64 * 0.1];
+                IndexStar::new(
+                    StarId::usnob(0, i).unwrap(),
+                    position,
+                    10.0,
+                    Motion::default(),
+                    0,
+                )
+            })
+            .collect::<Vec<_>>();
+        let quad =
+            Quad::from_unit_vectors([0, 1, 2, 3].map(|i| (stars[i].xyz(), stars[i].clone())))
+                .unwrap();
+        let metadata = IndexMetadata {
+            nside: 8,
+            scale_range: (1.0, 60.0),
+            catalog: "USNO-B1.0".to_string(),
+            band: "R".to_string(),
+            tile: None,
+            merged_tiles: Vec::new(),
+            settings: None,
+        };
+        // Stars sharing an ID would leave quads pointing at the wrong one
+        let duplicated = stars.iter().chain(&stars[..1]).cloned();
+        let error = Index::new(metadata.clone(), [].into_iter(), duplicated)
+            .save(&path)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("more than once"), "{}", error);
+
+        Index::new(metadata, [quad].into_iter(), stars.into_iter())
+            .save(&path)
+            .unwrap();
+
+        let mapped = MappedIndex::open(&path).unwrap();
+        let quad_stars = mapped.quad_stars.start;
+        drop(mapped);
+
+        let bytes = fs::read(&path).unwrap();
+        let (_, counts) = read_header(&bytes, &path).unwrap();
+
+        for n_stars in [u64::MAX, u64::MAX / 24 + 1, 1 << 60] {
+            let mut corrupt = bytes.clone();
+            corrupt[counts..counts + 8].copy_from_slice(&n_stars.to_le_bytes());
+            fs::write(&path, &corrupt).unwrap();
+            assert!(MappedIndex::open(&path).is_err());
+        }
+
+        let mut corrupt = bytes.clone();
+        corrupt[quad_stars + 4..quad_stars + 8].copy_from_slice(&4u32.to_le_bytes());
+        fs::write(&path, &corrupt).unwrap();
+        // Quads are only checked when they are read
+        let mapped = MappedIndex::open(&path).unwrap();
+        let error = mapped.quad(0).err().unwrap().to_string();
+        assert!(error.contains("quad 0"), "{}", error);
+        assert!(mapped.verify().is_err());
+        assert!(Index::load(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
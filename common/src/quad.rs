@@ -1,9 +1,11 @@
 use itertools::Itertools;
-use nalgebra::{Matrix2, Vector2};
+use nalgebra::{Matrix2, Vector2, Vector3};
 use serde::{Deserialize, Serialize};
 use std::f64::consts::SQRT_2;
 use std::fmt::Debug;
 
+use crate::sphere::{centroid, project_to_tangent_plane, radec_to_xyz};
+
 fn arrange<T>(items: [T; 4], arrangement: &[usize; 4]) -> impl Iterator<Item = T> {
     items
         .into_iter()
@@ -17,12 +19,28 @@ fn arrange<T>(items: [T; 4], arrangement: &[usize; 4]) -> impl Iterator<Item = T
         .map(|(_, item)| item)
 }
 
-type GHash = [f64; 4];
+pub type GHash = [f64; 4];
+
+/// Expected distance in code space between the geometric hash of a quad and
+/// the hash of the same quad after displacing each of its stars by noise with
+/// standard deviation `sigma` along each axis.
+///
+/// `scale` is the distance between A and B, in the same units as `sigma`.
+///
+/// The hash maps AB onto a segment of length sqrt(2), so displacing C or D by
+/// `sigma` moves its code by about sqrt(2) * sigma / scale. Displacing A or B
+/// moves the whole coordinate frame, which adds roughly 3/4 of that variance
+/// again for C and D lying within the circle around AB. Summed over the four
+/// code dimensions this gives a variance of 14 * (sigma / scale)^2.
+pub fn code_tolerance(sigma: f64, scale: f64) -> f64 {
+    14f64.sqrt() * sigma / scale
+}
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Quad<Star> {
     stars: [Star; 4],
     ghash: GHash,
+    scale: f64,
 }
 
 impl<Star: Debug> Quad<Star> {
@@ -30,6 +48,12 @@ impl<Star: Debug> Quad<Star> {
         let star_positions = [stars[0].0, stars[1].0, stars[2].0, stars[3].0];
 
         if let Some((ghash, arrangement)) = Self::compute_ghash(&star_positions) {
+            let (a, b) = (
+                star_positions[arrangement[0]],
+                star_positions[arrangement[1]],
+            );
+            let scale = Vector2::new(a.0 - b.0, a.1 - b.1).norm();
+
             // Get stars into the order of the arrangement
             let arranged_stars = arrange(stars, &arrangement)
                 .map(|(_, star)| star)
@@ -39,12 +63,53 @@ impl<Star: Debug> Quad<Star> {
 
             Some(Self {
                 ghash,
+                scale,
                 stars: arranged_stars,
             })
         } else {
             None
         }
     }
+
+    /// Reassemble a quad from its already arranged stars and precomputed hash.
+    pub(crate) fn from_parts(stars: [Star; 4], ghash: GHash, scale: f64) -> Self {
+        Self {
+            stars,
+            ghash,
+            scale,
+        }
+    }
+
+    /// Replace the quad's stars, keeping their arrangement.
+    pub(crate) fn map_stars<T>(self, f: impl FnMut(Star) -> T) -> Quad<T> {
+        Quad {
+            stars: self.stars.map(f),
+            ghash: self.ghash,
+            scale: self.scale,
+        }
+    }
+
+    /// Build a quad from stars given as unit vectors on the celestial sphere.
+    ///
+    /// The stars are projected onto the plane tangent to the sphere at their
+    /// centroid before hashing, so the hash only depends on the stars' relative
+    /// arrangement on the sky and not on where on the sky they are.
+    pub fn from_unit_vectors(stars: [(Vector3<f64>, Star); 4]) -> Option<Self> {
+        let center = centroid(&[stars[0].0, stars[1].0, stars[2].0, stars[3].0]);
+
+        let mut projected = Vec::with_capacity(4);
+        for (position, star) in stars {
+            projected.push((project_to_tangent_plane(&center, &position)?, star));
+        }
+
+        Self::new(projected.try_into().unwrap())
+    }
+
+    /// Build a quad from stars given as (RA, Dec) in degrees.
+    pub fn from_radec(stars: [((f64, f64), Star); 4]) -> Option<Self> {
+        Self::from_unit_vectors(stars.map(|((ra, dec), star)| (radec_to_xyz(ra, dec), star)))
+    }
+
     /// Compute the geometric hash of the given set of stars.
     ///
     /// The geometric hash works as follows:
@@ -135,6 +200,12 @@ impl<Star: Debug> Quad<Star> {
         self.ghash
     }
 
+    /// Distance between A and B in the units the quad was built from.
+    /// For quads built on the sphere, this is in radians.
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
     pub fn assert_invariants(&self) {
         // Invariant 2
         let mid = Vector2::new(0.5, 0.5);
@@ -149,11 +220,131 @@ impl<Star: Debug> Quad<Star> {
 
 #[cfg(test)]
 mod tests {
+    use std::f64::consts::PI;
+
     use itertools::iproduct;
-    use nalgebra::Vector4;
+    use nalgebra::{Rotation2, Rotation3, Vector4};
+    use proptest::prelude::*;
 
     use super::*;
 
+    /// Distance from the quad to the nearest boundary of its canonical
+    /// arrangement, below which rounding errors may legitimately flip it.
+    fn arrangement_margin(stars: &[(f64, f64); 4]) -> Option<f64> {
+        let (ghash, _) = Quad::<()>::compute_ghash(stars)?;
+        let [cx, cy, dx, dy] = ghash;
+        let mid = Vector2::new(0.5, 0.5);
+
+        let mut distances = (0..4)
+            .tuple_combinations()
+            .map(|(i, j)| Vector2::new(stars[i].0 - stars[j].0, stars[i].1 - stars[j].1).norm())
+            .sorted_by(|a, b| b.partial_cmp(a).unwrap());
+        let ab = distances.next().unwrap();
+        let second = distances.next().unwrap();
+
+        Some(
+            [
+                (ab - second) / ab,
+                dx - cx,
+                1.0 - cx - dx,
+                SQRT_2 / 2.0 - (Vector2::new(cx, cy) - mid).norm(),
+                SQRT_2 / 2.0 - (Vector2::new(dx, dy) - mid).norm(),
+            ]
+            .into_iter()
+            .fold(f64::INFINITY, f64::min),
+        )
+    }
+
+    fn star_positions() -> impl Strategy<Value = [(f64, f64); 4]> {
+        prop::array::uniform4((-100.0..100.0, -100.0..100.0))
+    }
+
+    proptest! {
+        #[test]
+        fn prop_similarity_invariance(
+            stars in star_positions(),
+            permutation in Just([0, 1, 2, 3]).prop_shuffle(),
+            angle in 0.0..std::f64::consts::TAU,
+            log_scale in -3.0..3.0f64,
+            translation in (-1e5..1e5, -1e5..1e5),
+        ) {
+            let margin = arrangement_margin(&stars);
+            prop_assume!(margin.is_some_and(|m| m > 1e-6));
+
+            let (original_ghash, _) = Quad::<()>::compute_ghash(&stars).unwrap();
+            let rotation = Rotation2::new(angle);
+            let scale = 10f64.powf(log_scale);
+            let translation = Vector2::new(translation.0, translation.1);
+
+            let transformed = permutation.map(|idx| {
+                let v = rotation * Vector2::new(stars[idx].0, stars[idx].1) * scale + translation;
+                ((v[0], v[1]), idx)
+            });
+
+            let quad = Quad::new(transformed).unwrap();
+            quad.assert_invariants();
+
+            let distance = Vector4::from(quad.ghash).metric_distance(&Vector4::from(original_ghash));
+            prop_assert!(distance < 1e-6, "ghash moved by {}", distance);
+            let original_scale = Quad::new(stars.map(|s| (s, ()))).unwrap().scale();
+            prop_assert!((quad.scale() / scale - original_scale).abs() < 1e-6);
+        }
+
+        #[test]
+        fn prop_invariants_hold(stars in star_positions()) {
+            if let Some(quad) = Quad::new(stars.map(|s| (s, ()))) {
+                quad.assert_invariants();
+            }
+        }
+    }
+
+    #[test]
+    fn test_perturbation() {
+        let mut runner = proptest::test_runner::TestRunner::deterministic();
+        let rng = runner.rng();
+
+        // Box-Muller
+        let gaussian = |rng: &mut proptest::test_runner::TestRng| {
+            let (u, v): (f64, f64) = (rng.gen_range(f64::EPSILON..1.0), rng.gen());
+            (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+        };
+
+        let sigmas = [1e-4, 1e-3, 1e-2];
+        let mut ratios = vec![Vec::new(); sigmas.len()];
+
+        while ratios[0].len() < 2000 {
+            let stars: [(f64, f64); 4] =
+                std::array::from_fn(|_| (rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)));
+
+            // Quads close to an arrangement boundary flip under noise; that's
+            // what the quad filters are for, not what's being measured here
+            if !arrangement_margin(&stars).is_some_and(|m| m > 0.1) {
+                continue;
+            }
+
+            let quad = Quad::new(stars.map(|s| (s, ()))).unwrap();
+
+            for (sigma, ratios) in sigmas.iter().zip(ratios.iter_mut()) {
+                let noisy = stars
+                    .map(|(x, y)| ((x + sigma * gaussian(rng), y + sigma * gaussian(rng)), ()));
+                let noisy = Quad::new(noisy).unwrap();
+
+                let error = Vector4::from(noisy.ghash).metric_distance(&Vector4::from(quad.ghash));
+                ratios.push(error / code_tolerance(*sigma, quad.scale()));
+            }
+        }
+
+        let rms = ratios
+            .iter()
+            .map(|r| (r.iter().map(|r| r * r).sum::<f64>() / r.len() as f64).sqrt())
+            .collect::<Vec<_>>();
+
+        // The error scales linearly with the noise and matches the model
+        for r in rms {
+            assert!((0.8..1.2).contains(&r));
+        }
+    }
+
     #[test]
     fn test_permutations() {
         let quads = [
@@ -234,6 +425,49 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sphere_invariance() {
+        // Around the south pole, like b0000.cat, and straddling RA = 0
+        let quads = [
+            [
+                (10.0, -89.5),
+                (100.0, -89.2),
+                (250.0, -89.7),
+                (330.0, -89.4),
+            ],
+            [(359.5, 1.0), (0.3, 0.2), (0.9, 1.4), (359.8, -0.4)],
+            [
+                (359.0, -89.95),
+                (0.5, -89.9),
+                (1.0, -89.97),
+                (180.0, -89.99),
+            ],
+        ];
+
+        let rotations = [
+            Rotation3::identity(),
+            Rotation3::from_euler_angles(0.3, -1.2, 2.5),
+            Rotation3::from_euler_angles(PI / 2.0, 0.0, 0.0),
+            Rotation3::from_euler_angles(-2.0, 0.7, -0.1),
+        ];
+
+        for stars in quads.iter() {
+            let original = Quad::from_radec(stars.map(|s| (s, ()))).unwrap();
+            original.assert_invariants();
+
+            for rotation in rotations.iter() {
+                let rotated = stars.map(|(ra, dec)| (rotation * radec_to_xyz(ra, dec), ()));
+                let quad = Quad::from_unit_vectors(rotated).unwrap();
+
+                quad.assert_invariants();
+                assert!(
+                    Vector4::from(quad.ghash).metric_distance(&Vector4::from(original.ghash))
+                        < 1e-7
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_arrange() {
         assert!(arrange(['a', 'b', 'c', 'd'], &[0, 1, 2, 3]).eq(['a', 'b', 'c', 'd']));
@@ -0,0 +1,369 @@
+/// Quad construction with quality filtering
+use std::fmt::{self, Debug, Display, Formatter};
+
+use itertools::Itertools;
+use nalgebra::{Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::quad::Quad;
+
+/// Criteria for rejecting quads whose hashes would be overly sensitive to
+/// noise in the star positions.
+///
+/// Area and spacing are measured relative to the backbone AB, so the same
+/// filter works for quads built in pixels or on the sphere.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct QuadFilter {
+    /// Minimum area of the triangles ABC and ABD, in units of |AB|^2.
+    pub min_area: f64,
+    /// Minimum distance between any two stars, in units of |AB|.
+    pub min_spacing: f64,
+    /// Maximum difference in magnitude between the brightest and the faintest
+    /// star. Only applied when magnitudes are known.
+    pub max_magnitude_spread: Option<f32>,
+    /// Require the backbone stars A and B to be brighter than C and D, so the
+    /// backbone survives fainter stars going undetected. Only applied when
+    /// magnitudes are known.
+    pub magnitude_order: bool,
+}
+
+impl Default for QuadFilter {
+    fn default() -> Self {
+        Self {
+            min_area: 0.005,
+            min_spacing: 0.05,
+            max_magnitude_spread: None,
+            magnitude_order: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// The stars have no arrangement satisfying the hash invariants,
+    /// or can't be projected onto a common tangent plane
+    Invariants,
+    Area,
+    Spacing,
+    Magnitude,
+    Order,
+}
+
+impl QuadFilter {
+    /// Check a quad's arrangement, as encoded by its hash, against the filter.
+    pub fn check_geometry<Star: Debug>(&self, quad: &Quad<Star>) -> Result<(), Rejection> {
+        // In code space, A = (0, 0) and B = (1, 1), so |AB| = sqrt(2)
+        let [cx, cy, dx, dy] = quad.ghash();
+        let points = [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(1.0, 1.0),
+            Vector2::new(cx, cy),
+            Vector2::new(dx, dy),
+        ];
+
+        let ab_squared = 2.0;
+        let triangle_area = |p: Vector2<f64>| (p[0] - p[1]).abs() / 2.0;
+
+        if triangle_area(points[2]).min(triangle_area(points[3])) / ab_squared < self.min_area {
+            return Err(Rejection::Area);
+        }
+
+        let min_distance = points
+            .iter()
+            .tuple_combinations()
+            .map(|(a, b)| (a - b).norm())
+            .fold(f64::INFINITY, f64::min);
+
+        if min_distance / ab_squared.sqrt() < self.min_spacing {
+            return Err(Rejection::Spacing);
+        }
+
+        Ok(())
+    }
+
+    pub fn check_magnitudes(&self, magnitudes: &[f32; 4]) -> Result<(), Rejection> {
+        if let Some(max_spread) = self.max_magnitude_spread {
+            let (min, max) = magnitudes.iter().minmax().into_option().unwrap();
+
+            if max - min > max_spread {
+                return Err(Rejection::Magnitude);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the magnitudes of a quad's stars, in the order of its arrangement
+    /// A, B, C, D, against the ordering requirement.
+    pub fn check_order(&self, magnitudes: &[f32; 4]) -> Result<(), Rejection> {
+        if self.magnitude_order
+            && magnitudes[0].max(magnitudes[1]) > magnitudes[2].min(magnitudes[3])
+        {
+            return Err(Rejection::Order);
+        }
+
+        Ok(())
+    }
+}
+
+/// How many candidate quads were accepted, and how many each filter rejected.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QuadStats {
+    pub accepted: usize,
+    pub rejected_invariants: usize,
+    pub rejected_area: usize,
+    pub rejected_spacing: usize,
+    pub rejected_magnitude: usize,
+    pub rejected_order: usize,
+}
+
+impl QuadStats {
+    pub fn rejected(&self) -> usize {
+        self.rejected_invariants
+            + self.rejected_area
+            + self.rejected_spacing
+            + self.rejected_magnitude
+            + self.rejected_order
+    }
+
+    fn record(&mut self, rejection: Rejection) {
+        match rejection {
+            Rejection::Invariants => self.rejected_invariants += 1,
+            Rejection::Area => self.rejected_area += 1,
+            Rejection::Spacing => self.rejected_spacing += 1,
+            Rejection::Magnitude => self.rejected_magnitude += 1,
+            Rejection::Order => self.rejected_order += 1,
+        }
+    }
+}
+
+impl Display for QuadStats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} accepted, {} rejected (invariants: {}, area: {}, spacing: {}, magnitude: {}, order: {})",
+            self.accepted,
+            self.rejected(),
+            self.rejected_invariants,
+            self.rejected_area,
+            self.rejected_spacing,
+            self.rejected_magnitude,
+            self.rejected_order
+        )
+    }
+}
+
+/// Builds quads from candidate sets of four stars, applying a [`QuadFilter`]
+/// and keeping statistics on what was rejected and why.
+#[derive(Debug, Default, Clone)]
+pub struct QuadBuilder {
+    filter: QuadFilter,
+    stats: QuadStats,
+}
+
+impl QuadBuilder {
+    pub fn new(filter: QuadFilter) -> Self {
+        Self {
+            filter,
+            stats: QuadStats::default(),
+        }
+    }
+
+    /// Build a quad from stars on the sphere, see [`Quad::from_unit_vectors`].
+    pub fn build<Star: Debug>(
+        &mut self,
+        stars: [(Vector3<f64>, Star); 4],
+        magnitudes: Option<&[f32; 4]>,
+    ) -> Option<Quad<Star>> {
+        self.check(magnitudes, || Quad::from_unit_vectors(numbered(stars)))
+    }
+
+    /// Build a quad from stars in a plane, e.g. pixel coordinates, see [`Quad::new`].
+    pub fn build_planar<Star: Debug>(
+        &mut self,
+        stars: [((f64, f64), Star); 4],
+        magnitudes: Option<&[f32; 4]>,
+    ) -> Option<Quad<Star>> {
+        self.check(magnitudes, || Quad::new(numbered(stars)))
+    }
+
+    fn check<Star: Debug>(
+        &mut self,
+        magnitudes: Option<&[f32; 4]>,
+        build: impl FnOnce() -> Option<Quad<(usize, Star)>>,
+    ) -> Option<Quad<Star>> {
+        let result = magnitudes
+            .map_or(Ok(()), |m| self.filter.check_magnitudes(m))
+            .and_then(|_| build().ok_or(Rejection::Invariants))
+            .and_then(|quad| self.filter.check_geometry(&quad).map(|_| quad))
+            .and_then(|quad| {
+                let arranged = quad.get_stars().each_ref().map(|(i, _)| *i);
+                magnitudes
+                    .map_or(Ok(()), |m| self.filter.check_order(&arranged.map(|i| m[i])))
+                    .map(|_| quad.map_stars(|(_, star)| star))
+            });
+
+        match result {
+            Ok(quad) => {
+                self.stats.accepted += 1;
+                Some(quad)
+            }
+            Err(rejection) => {
+                self.stats.record(rejection);
+                None
+            }
+        }
+    }
+
+    pub fn filter(&self) -> &QuadFilter {
+        &self.filter
+    }
+
+    pub fn stats(&self) -> &QuadStats {
+        &self.stats
+    }
+}
+
+/// Tag stars with their position in `stars`, to find out which star a quad
+/// arranged where
+fn numbered<P, Star>(stars: [(P, Star); 4]) -> [(P, (usize, Star)); 4] {
+    let mut i = 0;
+    stars.map(|(position, star)| {
+        i += 1;
+        (position, (i - 1, star))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn planar(positions: [(f64, f64); 4]) -> [((f64, f64), ()); 4] {
+        positions.map(|p| (p, ()))
+    }
+
+    const GOOD: [(f64, f64); 4] = [(0.0, 0.0), (10.0, 0.0), (4.0, 3.0), (6.0, -2.0)];
+
+    #[test]
+    fn test_filters() {
+        let mut builder = QuadBuilder::new(QuadFilter {
+            min_area: 0.01,
+            min_spacing: 0.1,
+            max_magnitude_spread: Some(2.0),
+            magnitude_order: true,
+        });
+
+        let good = planar(GOOD);
+        let collinear = planar([(0.0, 0.0), (10.0, 0.0), (4.0, 0.05), (6.0, -2.0)]);
+        let crowded = planar([(0.0, 0.0), (10.0, 0.0), (4.0, 3.0), (4.2, 3.3)]);
+        let invalid = planar([(0.0, 0.0), (10.0, 0.0), (5.0, 4.9), (5.0, 5.1)]);
+
+        assert!(builder.build_planar(good, None).is_some());
+        assert!(builder
+            .build_planar(good, Some(&[12.0, 12.5, 13.5, 13.0]))
+            .is_some());
+        assert!(builder
+            .build_planar(good, Some(&[12.0, 13.0, 14.5, 12.5]))
+            .is_none());
+        assert!(builder
+            .build_planar(good, Some(&[12.0, 13.0, 13.5, 12.5]))
+            .is_none());
+        assert!(builder.build_planar(collinear, None).is_none());
+        assert!(builder.build_planar(crowded, None).is_none());
+        assert!(builder.build_planar(invalid, None).is_none());
+
+        assert_eq!(
+            *builder.stats(),
+            QuadStats {
+                accepted: 2,
+                rejected_invariants: 1,
+                rejected_area: 1,
+                rejected_spacing: 1,
+                rejected_magnitude: 1,
+                rejected_order: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_each_filter() {
+        // Each filter on its own, starting from one that accepts everything
+        let permissive = QuadFilter {
+            min_area: 0.0,
+            min_spacing: 0.0,
+            max_magnitude_spread: None,
+            magnitude_order: false,
+        };
+        let stats = |filter: QuadFilter, positions: [(f64, f64); 4], magnitudes: [f32; 4]| {
+            let mut builder = QuadBuilder::new(filter);
+            builder.build_planar(planar(positions), Some(&magnitudes));
+            *builder.stats()
+        };
+        let bright_backbone = [12.0, 12.5, 13.5, 13.0];
+
+        assert_eq!(stats(permissive, GOOD, bright_backbone).accepted, 1);
+
+        let area = QuadFilter {
+            min_area: 0.12,
+            ..permissive
+        };
+        assert_eq!(stats(area, GOOD, bright_backbone).rejected_area, 1);
+        assert_eq!(
+            stats(
+                area,
+                [(0.0, 0.0), (10.0, 0.0), (4.0, 4.0), (6.0, -4.0)],
+                bright_backbone
+            )
+            .accepted,
+            1
+        );
+
+        let spacing = QuadFilter {
+            min_spacing: 0.47,
+            ..permissive
+        };
+        assert_eq!(stats(spacing, GOOD, bright_backbone).rejected_spacing, 1);
+        assert_eq!(
+            stats(
+                spacing,
+                [(0.0, 0.0), (10.0, 0.0), (3.0, 4.0), (7.0, -4.0)],
+                bright_backbone
+            )
+            .accepted,
+            1
+        );
+
+        let spread = QuadFilter {
+            max_magnitude_spread: Some(1.0),
+            ..permissive
+        };
+        assert_eq!(
+            stats(spread, GOOD, [12.0, 12.5, 13.5, 13.0]).rejected_magnitude,
+            1
+        );
+        assert_eq!(stats(spread, GOOD, [12.0, 12.5, 13.0, 12.8]).accepted, 1);
+
+        // The backbone is the widest pair, wherever it is passed
+        let order = QuadFilter {
+            magnitude_order: true,
+            ..permissive
+        };
+        assert_eq!(
+            stats(order, GOOD, [12.0, 13.5, 12.5, 13.0]).rejected_order,
+            1
+        );
+        assert_eq!(stats(order, GOOD, [13.0, 12.0, 13.5, 14.0]).accepted, 1);
+        let reordered = [GOOD[2], GOOD[0], GOOD[3], GOOD[1]];
+        assert_eq!(
+            stats(order, reordered, [13.0, 12.0, 13.5, 12.5]).accepted,
+            1
+        );
+        assert_eq!(
+            stats(order, reordered, [12.0, 13.0, 13.5, 12.5]).rejected_order,
+            1
+        );
+
+        let mut builder = QuadBuilder::new(order);
+        assert!(builder.build_planar(planar(GOOD), None).is_some());
+    }
+}
@@ -0,0 +1,92 @@
+/// Geometry on the celestial sphere
+use std::f64::consts::PI;
+
+use nalgebra::Vector3;
+
+/// Convert RA/Dec in degrees to a unit vector.
+pub fn radec_to_xyz(ra: f64, dec: f64) -> Vector3<f64> {
+    let (ra, dec) = (ra.to_radians(), dec.to_radians());
+
+    Vector3::new(dec.cos() * ra.cos(), dec.cos() * ra.sin(), dec.sin())
+}
+
+/// Convert a (not necessarily normalized) vector to RA/Dec in degrees,
+/// with RA in [0, 360).
+pub fn xyz_to_radec(xyz: &Vector3<f64>) -> (f64, f64) {
+    let xyz = xyz.normalize();
+    let ra = xyz[1].atan2(xyz[0]).to_degrees().rem_euclid(360.0);
+    let dec = xyz[2].clamp(-1.0, 1.0).asin().to_degrees();
+
+    (ra, dec)
+}
+
+/// Normalized mean of a set of unit vectors.
+pub fn centroid(points: &[Vector3<f64>]) -> Vector3<f64> {
+    points.iter().sum::<Vector3<f64>>().normalize()
+}
+
+/// Gnomonic (tangent-plane) projection of `point` onto the plane touching
+/// the sphere at `center`.
+///
+/// The returned coordinates are (east, north) in radians of the tangent plane.
+/// Points on or behind the plane's horizon can't be projected and yield None.
+pub fn project_to_tangent_plane(center: &Vector3<f64>, point: &Vector3<f64>) -> Option<(f64, f64)> {
+    let denom = point.dot(center);
+
+    if denom <= 0.0 {
+        return None;
+    }
+
+    // At the poles, east is ill-defined; any orthogonal basis will do
+    let east = Vector3::z().cross(center);
+    let east = if east.norm() < 1e-12 {
+        Vector3::y()
+    } else {
+        east.normalize()
+    };
+    let north = center.cross(&east);
+
+    Some((point.dot(&east) / denom, point.dot(&north) / denom))
+}
+
+/// Length of the chord between two points on the unit sphere `angle` radians
+/// apart, for searching angular distances in 3-D kd-trees.
+pub fn chord_length(angle: f64) -> f64 {
+    2.0 * (angle.min(PI) / 2.0).sin()
+}
+
+/// Angular distance between two unit vectors in radians.
+pub fn angular_distance(a: &Vector3<f64>, b: &Vector3<f64>) -> f64 {
+    a.cross(b).norm().atan2(a.dot(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_radec_roundtrip() {
+        for (ra, dec) in [(0.0, 0.0), (359.9, -89.9), (180.0, 45.0), (12.5, 90.0)] {
+            let (ra2, dec2) = xyz_to_radec(&radec_to_xyz(ra, dec));
+
+            assert!((dec - dec2).abs() < 1e-9);
+            if dec.abs() < 90.0 {
+                assert!((ra - ra2).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tangent_plane() {
+        let center = radec_to_xyz(0.0, -90.0);
+
+        assert_eq!(project_to_tangent_plane(&center, &center), Some((0.0, 0.0)));
+        assert_eq!(project_to_tangent_plane(&center, &-center), None);
+
+        // Stars one degree from the pole project to tan(1°) from the origin
+        for ra in [0.0, 90.0, 200.0, 359.0] {
+            let (x, y) = project_to_tangent_plane(&center, &radec_to_xyz(ra, -89.0)).unwrap();
+            assert!(((x * x + y * y).sqrt() - 1f64.to_radians().tan()).abs() < 1e-12);
+        }
+    }
+}
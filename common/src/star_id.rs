@@ -0,0 +1,152 @@
+/// Compact catalog identifiers for stars
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::error::AstroError;
+
+const CATALOG_SHIFT: u32 = 56;
+const ZONE_SHIFT: u32 = 40;
+const ZONE_MASK: u64 = 0xffff;
+const SEQUENCE_MASK: u64 = (1 << ZONE_SHIFT) - 1;
+
+/// Catalogs star IDs can refer to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Catalog {
+    USNOB = 1,
+}
+
+impl Catalog {
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(Catalog::USNOB),
+            _ => None,
+        }
+    }
+}
+
+/// A star's catalog, zone and sequence number within the zone, packed into
+/// 64 bits: the catalog tag in the top 8 bits, the zone in the next 16 and
+/// the sequence number in the lowest 40.
+///
+/// For USNO-B, the zone is the 0.1° declination slice the star is in and the
+/// sequence number its position within the zone's file, and the ID displays
+/// as the usual designation, e.g. "0000-0000065".
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct StarId(u64);
+
+impl StarId {
+    /// Fails if `sequence` doesn't fit in 40 bits.
+    pub fn new(catalog: Catalog, zone: u16, sequence: u64) -> Result<Self> {
+        ensure!(
+            sequence <= SEQUENCE_MASK,
+            AstroError::new(&format!(
+                "Sequence number {} is too large for a star ID",
+                sequence
+            ))
+        );
+
+        Ok(Self(
+            (catalog as u64) << CATALOG_SHIFT | (zone as u64) << ZONE_SHIFT | sequence,
+        ))
+    }
+
+    pub fn usnob(zone: u16, sequence: u64) -> Result<Self> {
+        Self::new(Catalog::USNOB, zone, sequence)
+    }
+
+    pub fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+
+    /// None if the catalog tag is unknown
+    pub fn catalog(&self) -> Option<Catalog> {
+        Catalog::from_tag((self.0 >> CATALOG_SHIFT) as u8)
+    }
+
+    pub fn zone(&self) -> u16 {
+        ((self.0 >> ZONE_SHIFT) & ZONE_MASK) as u16
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.0 & SEQUENCE_MASK
+    }
+}
+
+impl Display for StarId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.catalog() {
+            Some(Catalog::USNOB) => write!(f, "{:04}-{:07}", self.zone(), self.sequence()),
+            None => write!(f, "{:#018x}", self.0),
+        }
+    }
+}
+
+/// Parse a USNO-B designation like "0000-0000065"
+impl FromStr for StarId {
+    type Err = anyhow::Error;
+
+    fn from_str(designation: &str) -> Result<Self> {
+        let error = || AstroError::new(&format!("Invalid USNO-B designation: {}", designation));
+
+        let (zone, sequence) = designation.split_once('-').ok_or_else(error)?;
+        ensure!(
+            zone.len() == 4
+                && sequence.len() == 7
+                && (zone.chars().chain(sequence.chars())).all(|c| c.is_ascii_digit()),
+            error()
+        );
+
+        Self::usnob(zone.parse()?, sequence.parse()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usnob_designations() {
+        for designation in ["0000-0000065", "1799-0123456", "0901-9999999"] {
+            let id = designation.parse::<StarId>().unwrap();
+
+            assert_eq!(id.catalog(), Some(Catalog::USNOB));
+            assert_eq!(id.to_string(), designation);
+            assert_eq!(StarId::from_raw(id.raw()), id);
+        }
+
+        let id = StarId::usnob(1234, 56).unwrap();
+        assert_eq!((id.zone(), id.sequence()), (1234, 56));
+        assert!(StarId::usnob(1, 2).unwrap() < StarId::usnob(1, 3).unwrap());
+        assert!(StarId::usnob(1, 999).unwrap() < StarId::usnob(2, 0).unwrap());
+
+        // Sequence numbers that don't fit aren't truncated
+        let id = StarId::usnob(u16::MAX, SEQUENCE_MASK).unwrap();
+        assert_eq!((id.zone(), id.sequence()), (u16::MAX, SEQUENCE_MASK));
+        assert!(StarId::usnob(0, SEQUENCE_MASK + 1).is_err());
+        assert!(StarId::usnob(0, u64::MAX).is_err());
+
+        for invalid in [
+            "",
+            "0000",
+            "0000-65",
+            "00a0-0000065",
+            "0000-0000065-1",
+            "-000-0000065",
+        ] {
+            assert!(invalid.parse::<StarId>().is_err());
+        }
+
+        assert_eq!(StarId::from_raw(42).catalog(), None);
+        assert_eq!(StarId::from_raw(42).to_string(), "0x000000000000002a");
+    }
+}
@@ -6,11 +6,15 @@ use std::{
     fs::File,
     io::{BufReader, Read},
     ops::Deref,
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 const USNOB_RECORD_SIZE: usize = 80;
 
+/// Epoch of the catalog's positions: they are at J2000, with proper motions
+/// applied from the mean epoch of the observations
+pub const POSITION_EPOCH: f32 = 2000.0;
+
 #[inline]
 #[allow(clippy::excessive_precision)]
 fn arcsec_to_degrees(arcsec: f64) -> f64 {
@@ -59,6 +63,37 @@ pub struct Observations {
     pub infrared: Option<Observation>,
 }
 
+/// Mean magnitude of the observations that were made
+fn mean_mag<const N: usize>(observations: [&Option<Observation>; N]) -> Option<f32> {
+    let mags = observations
+        .into_iter()
+        .flatten()
+        .map(|Observation { mag, .. }| *mag)
+        .collect::<Vec<_>>();
+
+    if mags.is_empty() {
+        None
+    } else {
+        Some(mags.iter().sum::<f32>() / mags.len() as f32)
+    }
+}
+
+impl Observations {
+    /// Blue magnitude, averaged over both epochs where there are two
+    pub fn bmag(&self) -> Option<f32> {
+        mean_mag([&self.blue1, &self.blue2])
+    }
+
+    /// Red magnitude, averaged over both epochs where there are two
+    pub fn rmag(&self) -> Option<f32> {
+        mean_mag([&self.red1, &self.red2])
+    }
+
+    pub fn imag(&self) -> Option<f32> {
+        mean_mag([&self.infrared])
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct USNOBObject {
     // Identifier used internally, not part of the USNO-B files
@@ -92,7 +127,8 @@ pub struct USNOBObject {
     // Motion probability
     pub pm_prob: f32,
 
-    // Epoch year, range from 1950 to 2050
+    // Mean epoch of the observations, range from 1950 to 2050. Positions are
+    // still given at J2000, see POSITION_EPOCH
     pub epoch: f32,
 
     // Number of detections; different meanings based on the value
@@ -292,6 +328,30 @@ impl USNOBFile {
     }
 }
 
+/// The catalog files among `paths`, and the .cat files in those that are
+/// directories, sorted by name.
+pub fn find_catalog_files(paths: &[impl AsRef<Path>]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        let path = path.as_ref();
+        if path.is_dir() {
+            for entry in path.read_dir()? {
+                let entry = entry?;
+                if entry.path().is_file() && entry.path().extension() == Some("cat".as_ref()) {
+                    files.push(entry.path());
+                }
+            }
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::read_to_string;
@@ -356,4 +416,35 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_mean_magnitudes() {
+        let file = USNOBFile::open(from_crate_root("testdata/b0000.cat")).unwrap();
+
+        for obj in file.iter() {
+            let observations = &obj.observations;
+            let mags = [&observations.red1, &observations.red2]
+                .into_iter()
+                .flatten()
+                .map(|o| o.mag)
+                .collect::<Vec<_>>();
+
+            match mags[..] {
+                [] => assert_eq!(observations.rmag(), None),
+                [mag] => assert_eq!(observations.rmag(), Some(mag)),
+                [a, b] => assert_eq!(observations.rmag(), Some((a + b) / 2.0)),
+                _ => unreachable!(),
+            }
+            assert_eq!(
+                observations.imag(),
+                observations.infrared.as_ref().map(|o| o.mag)
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_catalog_files() {
+        let files = find_catalog_files(&[from_crate_root("testdata")]).unwrap();
+        assert_eq!(files, vec![from_crate_root("testdata/b0000.cat")]);
+    }
 }
@@ -8,3 +8,14 @@ pub fn from_crate_root(relative_path: &str) -> PathBuf {
     let full_path = Path::new(&manifest_dir).join(relative_path);
     full_path
 }
+
+/// A path in the temp directory for files written by tests, unique to the
+/// process so that concurrent test runs don't clobber each other's files
+#[cfg(any(test, feature = "test-util"))]
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!(
+        "astrometry-rs-test-{}-{}",
+        std::process::id(),
+        name
+    ))
+}
@@ -0,0 +1,473 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, Write},
+};
+
+use common::{
+    flat_kdtree,
+    healpix::{max_pixel_radius, pix2vec_nested, query_disc_nested, vec2pix_nested},
+    index::{BuildSettings, Index, IndexMetadata, IndexStar, SkyTile},
+    quad::Quad,
+    quad_builder::{QuadBuilder, QuadFilter, QuadStats},
+    sphere::{angular_distance, chord_length},
+};
+use itertools::Itertools;
+use nalgebra::Vector3;
+
+use crate::catalog::{Band, CatalogStar};
+
+#[derive(Clone)]
+pub struct BuildParameters {
+    pub nside: u32,
+    /// Faintest magnitude of the catalog stars to build from. Stars are
+    /// selected by magnitude as they are read, this only records it.
+    pub max_mag: f32,
+    /// Range of quad scales in arcminutes
+    pub scale_range: (f64, f64),
+    /// HEALPix resolution stars are uniformized at
+    pub uniformize_nside: u32,
+    /// How many stars to keep per uniformization cell, i.e. the number of sweeps
+    pub stars_per_cell: usize,
+    /// How many quads to build per HEALPix cell
+    pub quads_per_cell: usize,
+    pub filter: QuadFilter,
+    /// Only build quads in this part of the sky
+    pub tile: Option<SkyTile>,
+}
+
+/// Distance in radians from the center of `tile` within which stars of its
+/// quads may lie, up to `max_scale` arcminutes outside of it.
+pub fn tile_reach(tile: &SkyTile, max_scale: f64) -> f64 {
+    tile.radius() + (max_scale / 60.0).to_radians()
+}
+
+/// Distance in radians from the center of `tile` within which the
+/// uniformization cells of the stars it needs lie. Stars are uniformized
+/// against all the stars of their cell, so a tile needs the whole of every
+/// cell reaching within [`tile_reach`] of it.
+pub fn cell_reach(tile: &SkyTile, params: &BuildParameters) -> f64 {
+    tile_reach(tile, params.scale_range.1) + max_pixel_radius(params.uniformize_nside)
+}
+
+/// Center of the uniformization cell `point` lies in
+pub fn cell_center(point: &Vector3<f64>, params: &BuildParameters) -> Vector3<f64> {
+    let nside = params.uniformize_nside;
+    pix2vec_nested(nside, vec2pix_nested(nside, point))
+}
+
+/// Keep only the stars needed to build `tile`: those of the uniformization
+/// cells within [`cell_reach`] of it.
+pub fn restrict_to_tile(
+    stars: Vec<CatalogStar>,
+    tile: &SkyTile,
+    params: &BuildParameters,
+) -> Vec<CatalogStar> {
+    let center = tile.center();
+    let radius = cell_reach(tile, params);
+
+    stars
+        .into_iter()
+        .filter(|s| angular_distance(&center, &cell_center(&s.xyz(), params)) <= radius)
+        .collect()
+}
+
+/// Select stars evenly over the sky in round-robin sweeps, so that dense
+/// regions of the sky don't dominate the index: sweep 0 takes the brightest
+/// star of every HEALPix cell, sweep 1 the second brightest, and so on for
+/// `sweeps` sweeps. The stars are returned in sweep order, brightest first
+/// within each sweep, and are labeled with their sweep.
+pub fn uniformize(stars: Vec<CatalogStar>, nside: u32, sweeps: usize) -> Vec<CatalogStar> {
+    let mut cells: HashMap<u64, Vec<CatalogStar>> = HashMap::new();
+
+    for star in stars {
+        cells
+            .entry(vec2pix_nested(nside, &star.xyz()))
+            .or_default()
+            .push(star);
+    }
+
+    // Faintest first, so each sweep can pop the brightest remaining star
+    let mut cells = cells
+        .into_iter()
+        .sorted_by_key(|(cell, _)| *cell)
+        .map(|(_, mut stars)| {
+            stars.sort_by(|a, b| b.mag.total_cmp(&a.mag));
+            stars
+        })
+        .collect::<Vec<_>>();
+
+    let mut selected = Vec::new();
+
+    for sweep in 0..sweeps {
+        let mut swept = cells
+            .iter_mut()
+            .filter_map(|stars| stars.pop())
+            .collect::<Vec<_>>();
+
+        if swept.is_empty() {
+            break;
+        }
+
+        swept.sort_by(|a, b| a.mag.total_cmp(&b.mag));
+        for star in swept.iter_mut() {
+            star.sweep = sweep as u32;
+        }
+
+        selected.append(&mut swept);
+    }
+
+    selected
+}
+
+/// Build quads from `stars`.
+///
+/// Each quad is assigned to the HEALPix cell containing the midpoint of its
+/// backbone AB, and for each cell, quads are built from the stars of the
+/// earliest sweeps first, brightest first within a sweep, until
+/// `quads_per_cell` is reached.
+pub fn build_quads(
+    stars: &[CatalogStar],
+    params: &BuildParameters,
+) -> (Vec<Quad<IndexStar>>, QuadStats) {
+    let (min_scale, max_scale) = (
+        (params.scale_range.0 / 60.0).to_radians(),
+        (params.scale_range.1 / 60.0).to_radians(),
+    );
+
+    let positions = stars.iter().map(|s| s.xyz()).collect::<Vec<_>>();
+    let points = positions
+        .iter()
+        .map(|&p| p.into())
+        .collect::<Vec<[f64; 3]>>();
+    let order = flat_kdtree::kd_order(&points);
+    let tree = order.iter().map(|&i| points[i]).collect::<Vec<_>>();
+
+    // Any backbone with its midpoint in a cell has both stars within this
+    // distance of the cell's center, and so do C and D
+    let search_radius = max_pixel_radius(params.nside) + max_scale / 2.0;
+    let search_chord = chord_length(search_radius);
+
+    // A backbone's midpoint is within max_scale / 2 of both its stars, so it
+    // may lie in a cell that has no stars of its own
+    let cells = positions
+        .iter()
+        .flat_map(|p| query_disc_nested(params.nside, p, max_scale / 2.0))
+        .filter(|&cell| {
+            params
+                .tile
+                .is_none_or(|t| t.contains_cell(params.nside, cell))
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    let mut builder = QuadBuilder::new(params.filter);
+    let mut quads = Vec::new();
+
+    for (progress, &cell) in cells.iter().enumerate() {
+        let center: [f64; 3] = pix2vec_nested(params.nside, cell).into();
+
+        // Candidates, earliest sweep and brightest first
+        let candidates = flat_kdtree::within_radius(&tree, &center, search_chord)
+            .into_iter()
+            .map(|i| order[i])
+            .sorted_by(|&a, &b| {
+                stars[a]
+                    .sweep
+                    .cmp(&stars[b].sweep)
+                    .then(stars[a].mag.total_cmp(&stars[b].mag))
+            })
+            .collect::<Vec<_>>();
+
+        let mut n_quads = 0;
+
+        // Pairs ordered by their later star, so early-sweep backbones come first
+        'pairs: for (j, &b) in candidates.iter().enumerate() {
+            for &a in candidates[..j].iter() {
+                if n_quads >= params.quads_per_cell {
+                    break 'pairs;
+                }
+
+                let scale = angular_distance(&positions[a], &positions[b]);
+                if scale < min_scale || scale > max_scale {
+                    continue;
+                }
+
+                let midpoint = (positions[a] + positions[b]).normalize();
+                if vec2pix_nested(params.nside, &midpoint) != cell {
+                    continue;
+                }
+
+                let inside = candidates
+                    .iter()
+                    .filter(|&&c| c != a && c != b)
+                    .filter(|&&c| angular_distance(&midpoint, &positions[c]) < scale / 2.0)
+                    .collect::<Vec<_>>();
+
+                for (&c, &d) in inside.into_iter().tuple_combinations() {
+                    let members = [a, b, c, d];
+                    let magnitudes = members.map(|i| stars[i].mag);
+
+                    if let Some(quad) = builder.build(
+                        members.map(|i| (positions[i], stars[i].to_index_star())),
+                        Some(&magnitudes),
+                    ) {
+                        quads.push(quad);
+                        n_quads += 1;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if progress % 100 == 0 {
+            print!(
+                "\r    Progress: {:5.2}% ",
+                progress as f32 / cells.len() as f32 * 100.0
+            );
+            io::stdout().flush().unwrap();
+        }
+    }
+
+    println!("\r    Progress: 100.00% ");
+
+    (quads, *builder.stats())
+}
+
+/// Uniformize `stars` and, when building a tile, keep those quads in the tile
+/// can be built from, see [`tile_reach`].
+pub fn select_stars(stars: Vec<CatalogStar>, params: &BuildParameters) -> Vec<CatalogStar> {
+    let stars = uniformize(stars, params.uniformize_nside, params.stars_per_cell);
+
+    match params.tile {
+        Some(tile) => {
+            let center = tile.center();
+            let radius = tile_reach(&tile, params.scale_range.1);
+
+            stars
+                .into_iter()
+                .filter(|s| angular_distance(&center, &s.xyz()) <= radius)
+                .collect()
+        }
+        None => stars,
+    }
+}
+
+/// Metadata of the indexes built with `params` from stars of `band`
+pub fn index_metadata(params: &BuildParameters, band: Band) -> IndexMetadata {
+    IndexMetadata {
+        nside: params.nside,
+        scale_range: params.scale_range,
+        catalog: "USNO-B1.0".to_string(),
+        band: band.name().to_string(),
+        tile: params.tile,
+        merged_tiles: Vec::new(),
+        settings: Some(BuildSettings {
+            max_mag: params.max_mag,
+            uniformize_nside: params.uniformize_nside,
+            stars_per_cell: params.stars_per_cell,
+            quads_per_cell: params.quads_per_cell,
+            filter: params.filter,
+        }),
+    }
+}
+
+/// Uniformize `stars` and build an index of quads from them.
+pub fn build_index(stars: Vec<CatalogStar>, params: &BuildParameters, band: Band) -> Index {
+    println!("Uniformizing {} stars", stars.len());
+    let stars = select_stars(stars, params);
+
+    println!("Building quads from {} stars", stars.len());
+    let start = std::time::Instant::now();
+    let (quads, stats) = build_quads(&stars, params);
+    println!("    Quads: {} ({}s)", stats, start.elapsed().as_secs());
+
+    Index::new(
+        index_metadata(params, band),
+        quads.into_iter(),
+        stars.iter().map(|s| s.to_index_star()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{index::Motion, star_id::StarId};
+
+    use super::*;
+
+    fn star(i: u64, ra: f64, dec: f64, mag: f32) -> CatalogStar {
+        CatalogStar {
+            id: StarId::usnob(0, i).unwrap(),
+            ra,
+            dec,
+            mag,
+            motion: Motion::default(),
+            sweep: 0,
+        }
+    }
+
+    fn params(nside: u32) -> BuildParameters {
+        BuildParameters {
+            nside,
+            max_mag: 18.0,
+            scale_range: (10.0, 60.0),
+            uniformize_nside: nside,
+            stars_per_cell: 10,
+            quads_per_cell: 10,
+            filter: QuadFilter::default(),
+            tile: None,
+        }
+    }
+
+    #[test]
+    fn test_uniformize() {
+        // Three stars in one cell and one in another
+        let stars = vec![
+            star(0, 10.0, 10.0, 12.0),
+            star(1, 10.01, 10.0, 11.0),
+            star(2, 10.0, 10.01, 13.0),
+            star(3, 200.0, -40.0, 14.0),
+        ];
+
+        let selected = uniformize(stars.clone(), 8, 2);
+        let picks = selected
+            .iter()
+            .map(|s| (s.id.sequence(), s.sweep))
+            .collect::<Vec<_>>();
+        assert_eq!(picks, vec![(1, 0), (3, 0), (0, 1)]);
+
+        assert!(uniformize(stars, 8, 0).is_empty());
+    }
+
+    #[test]
+    fn test_quads_in_empty_cells() {
+        // A backbone 40' long with C and D well off its midpoint, so that no
+        // star lies in the cell the quad belongs to
+        let stars = vec![
+            star(0, 100.0, 0.0, 10.0),
+            star(1, 100.0 + 40.0 / 60.0, 0.0, 10.5),
+            star(2, 100.0 + 15.0 / 60.0, 12.0 / 60.0, 11.0),
+            star(3, 100.0 + 24.0 / 60.0, -13.0 / 60.0, 11.5),
+        ];
+        let params = params(512);
+
+        let midpoint = (stars[0].xyz() + stars[1].xyz()).normalize();
+        let cell = vec2pix_nested(params.nside, &midpoint);
+        assert!(stars
+            .iter()
+            .all(|s| vec2pix_nested(params.nside, &s.xyz()) != cell));
+
+        let (quads, stats) = build_quads(&stars, &params);
+        assert_eq!(quads.len(), 1, "{}", stats);
+
+        // No quad is built in a tile that doesn't contain the midpoint
+        let tile = SkyTile {
+            nside: 512,
+            pixel: vec2pix_nested(512, &stars[0].xyz()),
+        };
+        let (quads, _) = build_quads(
+            &stars,
+            &BuildParameters {
+                tile: Some(tile),
+                ..params
+            },
+        );
+        assert!(quads.is_empty());
+    }
+
+    #[test]
+    fn test_scale_range() {
+        let stars = vec![
+            star(0, 100.0, 0.0, 10.0),
+            star(1, 100.0 + 40.0 / 60.0, 0.0, 10.5),
+            star(2, 100.0 + 15.0 / 60.0, 12.0 / 60.0, 11.0),
+            star(3, 100.0 + 24.0 / 60.0, -13.0 / 60.0, 11.5),
+        ];
+
+        for (scale_range, n_quads) in [((10.0, 60.0), 1), ((45.0, 60.0), 0), ((5.0, 30.0), 0)] {
+            let params = BuildParameters {
+                scale_range,
+                ..params(64)
+            };
+            assert_eq!(build_quads(&stars, &params).0.len(), n_quads);
+        }
+    }
+
+    #[test]
+    fn test_restrict_to_tile() {
+        let tile = SkyTile {
+            nside: 4,
+            pixel: 100,
+        };
+        let center = tile.center();
+        let (ra, dec) = common::sphere::xyz_to_radec(&center);
+
+        let stars = vec![
+            star(0, ra, dec, 10.0),
+            star(1, ra, dec + tile.radius().to_degrees() + 0.5, 10.0),
+            star(2, ra, dec + tile.radius().to_degrees() + 2.0, 10.0),
+        ];
+
+        let params = BuildParameters {
+            scale_range: (10.0, 60.0),
+            ..params(256)
+        };
+        let kept = restrict_to_tile(stars, &tile, &params)
+            .iter()
+            .map(|s| s.id.sequence())
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_tile_uniformization() {
+        // Stars spread over base pixel 4 and its neighbours, dense enough for
+        // uniformization to drop some
+        let tile = SkyTile {
+            nside: 4,
+            pixel: 70,
+        };
+        let (ra, dec) = common::sphere::xyz_to_radec(&tile.center());
+        let stars = (0..4000)
+            .map(|i| {
+                let (x, y) = ((i * 7919 % 4000) as f64, (i * 104729 % 4001) as f64);
+                star(
+                    i,
+                    ra - 15.0 + x / 4000.0 * 30.0,
+                    dec - 15.0 + y / 4001.0 * 30.0,
+                    10.0 + (i * 31 % 97) as f32 / 10.0,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let params = BuildParameters {
+            scale_range: (10.0, 120.0),
+            stars_per_cell: 2,
+            ..params(16)
+        };
+        let tile_params = BuildParameters {
+            tile: Some(tile),
+            ..params.clone()
+        };
+
+        // The stars selected for the tile on its own are those selected from
+        // the whole catalog within its reach, in the same sweeps
+        let selected = |stars: Vec<CatalogStar>| {
+            stars
+                .into_iter()
+                .map(|s| (s.id.sequence(), s.sweep))
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        let whole = select_stars(stars.clone(), &params)
+            .into_iter()
+            .filter(|s| angular_distance(&tile.center(), &s.xyz()) <= tile_reach(&tile, 120.0))
+            .collect();
+        let restricted = restrict_to_tile(stars, &tile, &tile_params);
+
+        assert_eq!(
+            selected(select_stars(restricted, &tile_params)),
+            selected(whole)
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use common::{
+    index::{IndexStar, Motion},
+    sphere::radec_to_xyz,
+    star_id::StarId,
+    usnob::{self, find_catalog_files, USNOBFile, USNOBObject},
+};
+use futures::TryStreamExt;
+use nalgebra::Vector3;
+use sqlx::{Connection, Row, SqliteConnection};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Band {
+    B,
+    R,
+    I,
+}
+
+impl Band {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Band::B => "B",
+            Band::R => "R",
+            Band::I => "I",
+        }
+    }
+
+    fn db_column(&self) -> &'static str {
+        match self {
+            Band::B => "bmag",
+            Band::R => "rmag",
+            Band::I => "imag",
+        }
+    }
+
+    /// Magnitude of a USNO-B object in this band, the same as in the object DB
+    fn magnitude(&self, obj: &USNOBObject) -> Option<f32> {
+        match self {
+            Band::B => obj.observations.bmag(),
+            Band::R => obj.observations.rmag(),
+            Band::I => obj.observations.imag(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CatalogStar {
+    pub id: StarId,
+    pub ra: f64,
+    pub dec: f64,
+    pub mag: f32,
+    pub motion: Motion,
+    /// Uniformization sweep the star was selected in
+    pub sweep: u32,
+}
+
+impl CatalogStar {
+    pub fn xyz(&self) -> Vector3<f64> {
+        radec_to_xyz(self.ra, self.dec)
+    }
+
+    /// A USNO-B object with its magnitude in the band of the index
+    pub fn from_usnob(obj: &USNOBObject, mag: f32) -> Result<Self> {
+        Ok(Self {
+            id: obj.usnob_id.parse()?,
+            ra: obj.ra,
+            dec: obj.dec,
+            mag,
+            motion: Motion {
+                proper_motion: [obj.pm_ra, obj.pm_dec],
+                epoch: usnob::POSITION_EPOCH,
+            },
+            sweep: 0,
+        })
+    }
+
+    pub fn to_index_star(&self) -> IndexStar {
+        IndexStar::new(
+            self.id,
+            [self.ra, self.dec],
+            self.mag,
+            self.motion,
+            self.sweep,
+        )
+    }
+}
+
+/// Pass all stars brighter than `max_mag` in `band` from USNO-B catalog files
+/// or directories containing them to `visit`, one file at a time.
+pub fn scan_usnob_files(
+    paths: &[impl AsRef<Path>],
+    band: Band,
+    max_mag: f32,
+    mut visit: impl FnMut(CatalogStar) -> Result<()>,
+) -> Result<()> {
+    for path in find_catalog_files(paths)? {
+        println!("    Reading {:?}", path);
+
+        let file = USNOBFile::open(&path)?;
+
+        for obj in file.iter() {
+            let Some(mag) = band.magnitude(&obj).filter(|&mag| mag <= max_mag) else {
+                continue;
+            };
+
+            visit(CatalogStar::from_usnob(&obj, mag)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read all stars brighter than `max_mag` in `band` from USNO-B catalog files
+/// or directories containing them.
+pub fn read_usnob_files(
+    paths: &[impl AsRef<Path>],
+    band: Band,
+    max_mag: f32,
+) -> Result<Vec<CatalogStar>> {
+    let mut stars = Vec::new();
+
+    scan_usnob_files(paths, band, max_mag, |star| {
+        stars.push(star);
+        Ok(())
+    })?;
+
+    Ok(stars)
+}
+
+/// Pass all stars brighter than `max_mag` in `band` from the object DB to
+/// `visit`, without loading them all at once.
+pub async fn scan_database(
+    band: Band,
+    max_mag: f32,
+    mut visit: impl FnMut(CatalogStar) -> Result<()>,
+) -> Result<()> {
+    let mut connection = SqliteConnection::connect(&dotenvy::var("DATABASE_URL")?).await?;
+
+    let sql = format!(
+        "SELECT usnob_id, ra, dec, pm_ra, pm_dec, {0} AS mag FROM object WHERE {0} IS NOT NULL AND {0} <= $1",
+        band.db_column()
+    );
+
+    let mut rows = sqlx::query(&sql).bind(max_mag).fetch(&mut connection);
+    let mut count = 0;
+
+    while let Some(row) = rows.try_next().await? {
+        visit(CatalogStar {
+            id: row.try_get::<&str, _>("usnob_id")?.parse()?,
+            ra: row.try_get("ra")?,
+            dec: row.try_get("dec")?,
+            mag: row.try_get::<f64, _>("mag")? as f32,
+            motion: Motion {
+                proper_motion: [
+                    row.try_get::<f64, _>("pm_ra")? as f32,
+                    row.try_get::<f64, _>("pm_dec")? as f32,
+                ],
+                epoch: usnob::POSITION_EPOCH,
+            },
+            sweep: 0,
+        })?;
+
+        count += 1;
+        if count % 100_000 == 0 {
+            print!("\r    {} stars", count);
+            io::stdout().flush().unwrap();
+        }
+    }
+
+    println!("\r    {} stars", count);
+
+    Ok(())
+}
+
+/// Read all stars brighter than `max_mag` in `band` from the object DB.
+pub async fn read_database(band: Band, max_mag: f32) -> Result<Vec<CatalogStar>> {
+    let mut stars = Vec::new();
+
+    scan_database(band, max_mag, |star| {
+        stars.push(star);
+        Ok(())
+    })
+    .await?;
+
+    Ok(stars)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{sphere::angular_distance, util::from_crate_root};
+
+    use super::*;
+
+    #[test]
+    fn test_usnob_proper_motion() {
+        // 0000-0000173 moves 0.084"/yr east and 0.150"/yr south, its mean
+        // epoch of observation is 1986.9
+        let file = USNOBFile::open(from_crate_root("../common/testdata/b0000.cat")).unwrap();
+        let obj = file
+            .iter()
+            .find(|obj| obj.usnob_id == "0000-0000173")
+            .unwrap();
+        assert_eq!(obj.epoch, 1986.9);
+
+        let star = CatalogStar::from_usnob(&obj, Band::R.magnitude(&obj).unwrap())
+            .unwrap()
+            .to_index_star();
+        assert_eq!(star.motion().epoch, 2000.0);
+
+        // The position VizieR gives for J2000
+        let j2000 = radec_to_xyz(194.118667, -89.951298);
+        let arcsec = |xyz: &Vector3<f64>| angular_distance(xyz, &j2000).to_degrees() * 3600.0;
+        assert!(arcsec(&star.xyz_at(2000.0)) < 0.01);
+
+        // 0.1719"/yr, 13.1 years before and 20 years after J2000
+        let speed = 0.084f64.hypot(0.150);
+        assert!((arcsec(&star.xyz_at(1986.9)) - 13.1 * speed).abs() < 0.01);
+        assert!((arcsec(&star.xyz_at(2020.0)) - 20.0 * speed).abs() < 0.01);
+
+        let [_, dec] = star.position_at(2020.0);
+        assert!((dec - (-89.951298 - 20.0 * 0.150 / 3600.0)).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,321 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{ensure, Result};
+use clap::Parser;
+use common::{
+    error::AstroError,
+    healpix::npix,
+    index::{Index, SkyTile},
+    quad_builder::QuadFilter,
+};
+use dotenvy::dotenv;
+
+use index_builder::{
+    build::{build_index, index_metadata, restrict_to_tile, BuildParameters},
+    catalog::{self, Band},
+    tiles::TileSpill,
+};
+
+/// Build an index from the object DB, or from USNO-B catalog files if any are given
+#[derive(Parser, Debug)]
+struct Args {
+    /// Where to write the index
+    output: PathBuf,
+    /// USNO-B .cat files or directories containing them
+    #[arg(long)]
+    catalog: Vec<PathBuf>,
+    /// HEALPix resolution used to distribute quads
+    #[arg(long, default_value_t = 16)]
+    nside: u32,
+    /// HEALPix resolution used to uniformize stars, defaults to --nside
+    #[arg(long)]
+    uniformize_nside: Option<u32>,
+    /// Smallest quad scale (distance between A and B) in arcminutes
+    #[arg(long)]
+    scale_min: f64,
+    /// Largest quad scale in arcminutes
+    #[arg(long)]
+    scale_max: f64,
+    #[arg(long, value_enum, default_value_t = Band::R)]
+    band: Band,
+    /// Faintest magnitude to include
+    #[arg(long, default_value_t = 18.0)]
+    max_mag: f32,
+    /// Number of uniformization sweeps, i.e. stars kept per uniformization cell
+    #[arg(long, default_value_t = 10)]
+    stars_per_cell: usize,
+    #[arg(long, default_value_t = 10)]
+    quads_per_cell: usize,
+    #[arg(long, default_value_t = QuadFilter::default().min_area)]
+    min_area: f64,
+    #[arg(long, default_value_t = QuadFilter::default().min_spacing)]
+    min_spacing: f64,
+    #[arg(long)]
+    max_magnitude_spread: Option<f32>,
+    /// Only keep quads whose backbone stars are brighter than the other two
+    #[arg(long)]
+    magnitude_order: bool,
+    /// Only cover one HEALPix pixel (nested scheme) of this resolution
+    #[arg(long, requires = "tile")]
+    tile_nside: Option<u32>,
+    /// The pixel to cover
+    #[arg(long, requires = "tile_nside")]
+    tile: Option<u64>,
+    /// Build all pixels of this resolution as separate indexes, reading the
+    /// catalog once and holding only one tile's stars in memory. The output is
+    /// then a directory of tile indexes, which the solver searches as an index
+    /// set. They aren't merged since a merged index would have to fit in
+    /// memory. Tiles whose index already exists with the same parameters are
+    /// not rebuilt
+    #[arg(long, conflicts_with = "tile_nside")]
+    tiles_nside: Option<u32>,
+    /// Where to keep stars while building, defaults to the output
+    /// with a .work extension
+    #[arg(long, requires = "tiles_nside")]
+    work_dir: Option<PathBuf>,
+}
+
+impl Args {
+    fn tile(&self) -> Option<SkyTile> {
+        self.tile_nside
+            .zip(self.tile)
+            .map(|(nside, pixel)| SkyTile { nside, pixel })
+    }
+
+    fn params(&self) -> BuildParameters {
+        BuildParameters {
+            nside: self.nside,
+            max_mag: self.max_mag,
+            scale_range: (self.scale_min, self.scale_max),
+            uniformize_nside: self.uniformize_nside.unwrap_or(self.nside),
+            stars_per_cell: self.stars_per_cell,
+            quads_per_cell: self.quads_per_cell,
+            filter: QuadFilter {
+                min_area: self.min_area,
+                min_spacing: self.min_spacing,
+                max_magnitude_spread: self.max_magnitude_spread,
+                magnitude_order: self.magnitude_order,
+            },
+            tile: self.tile(),
+        }
+    }
+
+    /// Reject arguments no index can be built with.
+    fn check(&self) -> Result<()> {
+        let uniformize_nside = self.uniformize_nside.unwrap_or(self.nside);
+
+        ensure!(
+            self.nside.is_power_of_two() && uniformize_nside.is_power_of_two(),
+            AstroError::new(&format!(
+                "--nside {} and --uniformize-nside {} must be powers of two",
+                self.nside, uniformize_nside
+            ))
+        );
+
+        ensure!(
+            0.0 < self.scale_min && self.scale_min < self.scale_max,
+            AstroError::new(&format!(
+                "Invalid scale range {} to {} arcminutes",
+                self.scale_min, self.scale_max
+            ))
+        );
+
+        if let Some(tile) = self.tile() {
+            ensure!(
+                tile.nside.is_power_of_two()
+                    && tile.nside <= self.nside
+                    && tile.pixel < npix(tile.nside),
+                AstroError::new(&format!(
+                    "Tile {} at nside {} is invalid or coarser than --nside",
+                    tile.pixel, tile.nside
+                ))
+            );
+        }
+
+        if let Some(nside) = self.tiles_nside {
+            ensure!(
+                nside.is_power_of_two() && nside <= self.nside,
+                AstroError::new(&format!(
+                    "Tiles at nside {} are invalid or coarser than --nside",
+                    nside
+                ))
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Build every tile of resolution `nside` that contains stars as its own index.
+async fn build_tiles(args: &Args, mut params: BuildParameters, nside: u32) -> Result<()> {
+    let work_dir = args
+        .work_dir
+        .clone()
+        .unwrap_or_else(|| args.output.with_extension("work"));
+    fs::create_dir_all(&args.output)?;
+
+    println!("Distributing stars to tiles");
+    let mut spill = TileSpill::new(work_dir.join("stars"), nside, &params)?;
+    if args.catalog.is_empty() {
+        catalog::scan_database(args.band, args.max_mag, |star| spill.add(star)).await?;
+    } else {
+        catalog::scan_usnob_files(&args.catalog, args.band, args.max_mag, |star| {
+            spill.add(star)
+        })?;
+    }
+    spill.flush()?;
+
+    let tiles = spill.tiles();
+
+    for (i, tile) in tiles.iter().enumerate() {
+        let path = args
+            .output
+            .join(format!("tile-{}-{}.idx", tile.nside, tile.pixel));
+        println!("Tile {} ({}/{})", tile.pixel, i + 1, tiles.len());
+
+        params.tile = Some(*tile);
+
+        if path.exists() {
+            // Only tiles of an interrupted run with the same parameters are
+            // reused, anything else is rebuilt
+            match Index::read_metadata(&path) {
+                Ok(metadata) if metadata == index_metadata(&params, args.band) => {
+                    println!("    {:?} already exists", path);
+                    continue;
+                }
+                _ => println!("    {:?} was built differently, rebuilding it", path),
+            }
+        }
+
+        let index = build_index(spill.read(tile)?, &params, args.band);
+
+        if index.quad_index().is_empty() {
+            println!("    No quads");
+            continue;
+        }
+
+        // Tiles are written under a temporary name first, so an interrupted
+        // run doesn't leave partial tiles behind that look complete
+        let partial = path.with_extension("partial");
+        index.save(&partial)?;
+        fs::rename(&partial, &path)?;
+    }
+
+    spill.remove()?;
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+
+    let args = Args::parse();
+
+    args.check()?;
+
+    let tile = args.tile();
+
+    let params = args.params();
+
+    if let Some(nside) = args.tiles_nside {
+        return build_tiles(&args, params, nside).await;
+    }
+
+    println!("Reading stars");
+    let stars = if args.catalog.is_empty() {
+        catalog::read_database(args.band, args.max_mag).await?
+    } else {
+        catalog::read_usnob_files(&args.catalog, args.band, args.max_mag)?
+    };
+
+    let stars = match tile {
+        Some(tile) => restrict_to_tile(stars, &tile, &params),
+        None => stars,
+    };
+
+    let index = build_index(stars, &params, args.band);
+
+    println!("Writing {:?}", args.output);
+    index.save(&args.output)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use common::util::{from_crate_root, temp_path};
+
+    use super::*;
+
+    fn check(args: &str) -> Result<()> {
+        Args::try_parse_from(format!("index_builder out.idx {}", args).split_whitespace())?.check()
+    }
+
+    #[test]
+    fn test_check_args() {
+        assert!(check("--scale-min 5 --scale-max 30").is_ok());
+        assert!(check("--scale-min 5 --scale-max 30 --nside 32 --uniformize-nside 64").is_ok());
+        assert!(check("--scale-min 5 --scale-max 30 --tile-nside 2 --tile 47").is_ok());
+        assert!(check("--scale-min 5 --scale-max 30 --tiles-nside 4").is_ok());
+
+        assert!(check("--scale-min 5 --scale-max 30 --nside 12").is_err());
+        assert!(check("--scale-min 5 --scale-max 30 --uniformize-nside 48").is_err());
+        assert!(check("--scale-min 30 --scale-max 5").is_err());
+        assert!(check("--scale-min 5 --scale-max 5").is_err());
+        assert!(check("--scale-min 0 --scale-max 5").is_err());
+        assert!(check("--scale-min 5 --scale-max 30 --tile-nside 2 --tile 48").is_err());
+        assert!(check("--scale-min 5 --scale-max 30 --tile-nside 3 --tile 1").is_err());
+        assert!(check("--scale-min 5 --scale-max 30 --tiles-nside 32").is_err());
+        assert!(check("--scale-min 5 --scale-max 30 --tile 1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_changed_tiles() {
+        let output = temp_path("tiles");
+        let build = |options: &str| {
+            let args = Args::try_parse_from(
+                format!(
+                    "index_builder {} --catalog {} --nside 256 --scale-min 0.5 --scale-max 3 --tiles-nside 1 {}",
+                    output.display(),
+                    from_crate_root("../common/testdata/b0000.cat").display(),
+                    options
+                )
+                .split_whitespace(),
+            )
+            .unwrap();
+            async move { build_tiles(&args, args.params(), 1).await }
+        };
+        let tiles = || {
+            let mut tiles = fs::read_dir(&output)
+                .unwrap()
+                .map(|entry| {
+                    let path = entry.unwrap().path();
+                    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+                    (path, modified)
+                })
+                .collect::<Vec<_>>();
+            tiles.sort();
+            tiles
+        };
+
+        build("--max-mag 21").await.unwrap();
+        let first = tiles();
+        assert!(!first.is_empty());
+
+        // Same parameters, the tiles are reused
+        build("--max-mag 21").await.unwrap();
+        assert_eq!(tiles(), first);
+
+        build("--max-mag 19").await.unwrap();
+        let second = tiles();
+        for (path, modified) in &second {
+            assert!(first.iter().all(|(p, m)| p != path || m != modified));
+            let settings = Index::read_metadata(path).unwrap().settings.unwrap();
+            assert_eq!(settings.max_mag, 19.0);
+        }
+
+        fs::remove_dir_all(&output).unwrap();
+        fs::remove_dir_all(output.with_extension("work")).unwrap();
+    }
+}
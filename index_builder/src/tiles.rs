@@ -0,0 +1,235 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{self, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Result};
+use common::{
+    error::AstroError,
+    healpix::query_disc_nested,
+    index::{Motion, SkyTile},
+    sphere::angular_distance,
+    star_id::StarId,
+};
+
+use crate::{
+    build::{cell_center, cell_reach, BuildParameters},
+    catalog::CatalogStar,
+};
+
+/// How many stars to hold in memory before appending them to the tiles' files
+const SPILL_BUFFER: usize = 1 << 20;
+
+/// Size of a star in a spill file: ID, RA, Dec, magnitude, proper motion and epoch
+const RECORD_SIZE: usize = 40;
+
+fn write_star(writer: &mut impl Write, star: &CatalogStar) -> Result<()> {
+    writer.write_all(&star.id.raw().to_le_bytes())?;
+    writer.write_all(&star.ra.to_le_bytes())?;
+    writer.write_all(&star.dec.to_le_bytes())?;
+    writer.write_all(&star.mag.to_le_bytes())?;
+    writer.write_all(&star.motion.proper_motion[0].to_le_bytes())?;
+    writer.write_all(&star.motion.proper_motion[1].to_le_bytes())?;
+    writer.write_all(&star.motion.epoch.to_le_bytes())?;
+    Ok(())
+}
+
+fn read_star(record: &[u8]) -> CatalogStar {
+    let u64_at = |i: usize| u64::from_le_bytes(record[i..i + 8].try_into().unwrap());
+    let f32_at = |i: usize| f32::from_le_bytes(record[i..i + 4].try_into().unwrap());
+
+    CatalogStar {
+        id: StarId::from_raw(u64_at(0)),
+        ra: f64::from_bits(u64_at(8)),
+        dec: f64::from_bits(u64_at(16)),
+        mag: f32_at(24),
+        motion: Motion {
+            proper_motion: [f32_at(28), f32_at(32)],
+            epoch: f32_at(36),
+        },
+        sweep: 0,
+    }
+}
+
+/// Distributes catalog stars to the HEALPix tiles that need them, keeping one
+/// file of stars per tile in `dir` so the whole catalog never has to fit in
+/// memory.
+///
+/// Stars go to tiles by their uniformization cell, see
+/// [`crate::build::restrict_to_tile`],
+/// so that each tile has all the stars of the cells it is uniformized with.
+pub struct TileSpill {
+    dir: PathBuf,
+    nside: u32,
+    params: BuildParameters,
+    /// Distance from a tile's center within which it needs uniformization cells
+    reach: f64,
+    buffers: HashMap<u64, Vec<CatalogStar>>,
+    buffered: usize,
+    tiles: BTreeSet<u64>,
+}
+
+impl TileSpill {
+    /// Start spilling to `dir` for tiles of resolution `nside` built with
+    /// `params`, discarding any stars left there by a previous run.
+    pub fn new(dir: impl AsRef<Path>, nside: u32, params: &BuildParameters) -> Result<Self> {
+        let dir = dir.as_ref();
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            nside,
+            params: params.clone(),
+            reach: cell_reach(&SkyTile { nside, pixel: 0 }, params),
+            buffers: HashMap::new(),
+            buffered: 0,
+            tiles: BTreeSet::new(),
+        })
+    }
+
+    fn path(&self, pixel: u64) -> PathBuf {
+        self.dir.join(format!("{}.stars", pixel))
+    }
+
+    /// Add `star` to every tile it may be needed in.
+    pub fn add(&mut self, star: CatalogStar) -> Result<()> {
+        let cell = cell_center(&star.xyz(), &self.params);
+
+        for pixel in query_disc_nested(self.nside, &cell, self.reach) {
+            let tile = SkyTile {
+                nside: self.nside,
+                pixel,
+            };
+            if angular_distance(&tile.center(), &cell) <= self.reach {
+                self.buffers.entry(pixel).or_default().push(star.clone());
+                self.buffered += 1;
+            }
+        }
+
+        if self.buffered >= SPILL_BUFFER {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Append all buffered stars to their tiles' files.
+    pub fn flush(&mut self) -> Result<()> {
+        for (pixel, stars) in std::mem::take(&mut self.buffers) {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path(pixel))?;
+            let mut writer = BufWriter::new(file);
+
+            for star in &stars {
+                write_star(&mut writer, star)?;
+            }
+
+            writer.flush()?;
+            self.tiles.insert(pixel);
+        }
+
+        self.buffered = 0;
+
+        Ok(())
+    }
+
+    /// Tiles that received any stars, in pixel order.
+    pub fn tiles(&self) -> Vec<SkyTile> {
+        self.tiles
+            .iter()
+            .map(|&pixel| SkyTile {
+                nside: self.nside,
+                pixel,
+            })
+            .collect()
+    }
+
+    /// Read back the stars of `tile`, after [`Self::flush`].
+    pub fn read(&self, tile: &SkyTile) -> Result<Vec<CatalogStar>> {
+        let bytes = fs::read(self.path(tile.pixel))?;
+
+        ensure!(
+            bytes.len() % RECORD_SIZE == 0,
+            AstroError::new(&format!("{} is truncated", self.path(tile.pixel).display()))
+        );
+
+        Ok(bytes.chunks_exact(RECORD_SIZE).map(read_star).collect())
+    }
+
+    /// Delete the spilled stars.
+    pub fn remove(self) -> Result<()> {
+        fs::remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{quad_builder::QuadFilter, util::temp_path};
+
+    use super::*;
+
+    #[test]
+    fn test_spill_round_trip() {
+        let dir = temp_path("spill");
+        let params = BuildParameters {
+            nside: 16,
+            max_mag: 18.0,
+            scale_range: (10.0, 60.0),
+            uniformize_nside: 16,
+            stars_per_cell: 10,
+            quads_per_cell: 10,
+            filter: QuadFilter::default(),
+            tile: None,
+        };
+
+        let star = CatalogStar {
+            id: StarId::usnob(1799, 123456).unwrap(),
+            ra: 123.456789012345,
+            dec: -45.678901234567,
+            mag: 17.25,
+            motion: Motion {
+                proper_motion: [0.084, -0.15],
+                epoch: 2000.0,
+            },
+            sweep: 3,
+        };
+
+        let mut record = Vec::new();
+        write_star(&mut record, &star).unwrap();
+        assert_eq!(record.len(), RECORD_SIZE);
+
+        let read = read_star(&record);
+        assert_eq!(read.id, star.id);
+        assert_eq!((read.ra, read.dec), (star.ra, star.dec));
+        assert_eq!(read.mag, star.mag);
+        assert_eq!(read.motion, star.motion);
+        // Sweeps are assigned when tiles are built
+        assert_eq!(read.sweep, 0);
+
+        let mut spill = TileSpill::new(&dir, 2, &params).unwrap();
+        spill.add(star.clone()).unwrap();
+        spill.flush().unwrap();
+
+        let tiles = spill.tiles();
+        assert!(!tiles.is_empty());
+        for tile in &tiles {
+            let stars = spill.read(tile).unwrap();
+            assert_eq!(stars.len(), 1);
+            assert_eq!(stars[0].id, star.id);
+        }
+
+        fs::write(dir.join(format!("{}.stars", tiles[0].pixel)), &record[1..]).unwrap();
+        assert!(spill.read(&tiles[0]).is_err());
+
+        spill.remove().unwrap();
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,251 @@
+use std::{collections::BTreeMap, fs::File, io::BufWriter, path::Path};
+
+use anyhow::Result;
+use common::{
+    fits_bintable::{ColumnData, ColumnDef, FitsTableWriter},
+    index::IndexMetadata,
+    mapped_index::MappedIndex,
+    sphere::angular_distance,
+};
+use nalgebra::Vector3;
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct DumpStar {
+    pub designation: String,
+    pub ra: f64,
+    pub dec: f64,
+    pub mag: f32,
+    /// Proper motion in RA (times cos Dec) and Dec in arcseconds per year
+    pub pm_ra: f32,
+    pub pm_dec: f32,
+    pub epoch: f32,
+    pub sweep: u32,
+}
+
+#[derive(Serialize)]
+pub struct DumpQuad {
+    /// Positions of the stars A, B, C and D in the dump's star list
+    pub stars: [u32; 4],
+    pub code: [f64; 4],
+    /// Distance between A and B in arcminutes
+    pub scale: f64,
+}
+
+/// The stars and quads of a region of an index
+#[derive(Serialize)]
+pub struct Dump {
+    pub metadata: IndexMetadata,
+    pub stars: Vec<DumpStar>,
+    pub quads: Vec<DumpQuad>,
+}
+
+impl Dump {
+    /// Collect the stars within `radius` radians of `center`, and the quads
+    /// whose backbone's midpoint lies within it, along with their stars.
+    pub fn region(index: &MappedIndex, center: &Vector3<f64>, radius: f64) -> Result<Self> {
+        let positions = index.star_positions();
+        let xyz = |star: usize| Vector3::from(index.star_vectors()[star]);
+
+        let mut quads = Vec::new();
+        for q in 0..index.num_quads() {
+            let stars = index.quad_members(q)?;
+            if angular_distance(center, &(xyz(stars[0]) + xyz(stars[1])).normalize()) <= radius {
+                quads.push((q, stars));
+            }
+        }
+
+        // Position of each index star in the dump
+        let mut ids = BTreeMap::new();
+        for star in index.stars_within(center, radius) {
+            ids.insert(star, 0);
+        }
+        for (_, stars) in quads.iter() {
+            for &star in stars {
+                ids.insert(star, 0);
+            }
+        }
+        for (i, id) in ids.values_mut().enumerate() {
+            *id = i as u32;
+        }
+
+        let stars = ids
+            .keys()
+            .map(|&star| {
+                let [ra, dec] = positions[star];
+                let [pm_ra, pm_dec, epoch] = index.star_motions()[star];
+
+                DumpStar {
+                    designation: index.star_id(star).to_string(),
+                    ra,
+                    dec,
+                    mag: index.star_mags()[star],
+                    pm_ra,
+                    pm_dec,
+                    epoch,
+                    sweep: index.star_sweeps()[star],
+                }
+            })
+            .collect();
+
+        let quads = quads
+            .into_iter()
+            .map(|(q, stars)| DumpQuad {
+                stars: stars.map(|star| ids[&star]),
+                code: index.quad_codes()[q],
+                scale: index.quad_scales()[q].to_degrees() * 60.0,
+            })
+            .collect();
+
+        Ok(Self {
+            metadata: index.metadata().clone(),
+            stars,
+            quads,
+        })
+    }
+
+    pub fn write_json(&self, path: impl AsRef<Path>) -> Result<()> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    /// Write the stars and quads as the binary tables STARS and QUADS. Quads
+    /// refer to their stars by row number in STARS, starting at 0.
+    pub fn write_fits(&self, path: impl AsRef<Path>) -> Result<()> {
+        let width = self
+            .stars
+            .iter()
+            .map(|s| s.designation.len())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        let stars = [
+            ColumnData::new(
+                ColumnDef::new("DESIGNATION", &format!("{}A", width))?,
+                self.stars.iter().map(|s| &s.designation),
+            )?,
+            ColumnData::new(
+                ColumnDef::new("RA", "D")?.with_unit("deg"),
+                self.stars.iter().map(|s| s.ra),
+            )?,
+            ColumnData::new(
+                ColumnDef::new("DEC", "D")?.with_unit("deg"),
+                self.stars.iter().map(|s| s.dec),
+            )?,
+            ColumnData::new(
+                ColumnDef::new("MAG", "E")?,
+                self.stars.iter().map(|s| s.mag),
+            )?,
+            ColumnData::new(
+                ColumnDef::new("PMRA", "E")?.with_unit("arcsec/yr"),
+                self.stars.iter().map(|s| s.pm_ra),
+            )?,
+            ColumnData::new(
+                ColumnDef::new("PMDEC", "E")?.with_unit("arcsec/yr"),
+                self.stars.iter().map(|s| s.pm_dec),
+            )?,
+            ColumnData::new(
+                ColumnDef::new("EPOCH", "E")?,
+                self.stars.iter().map(|s| s.epoch),
+            )?,
+            ColumnData::new(
+                ColumnDef::new("SWEEP", "J")?,
+                self.stars.iter().map(|s| s.sweep),
+            )?,
+        ];
+
+        let quads = [
+            ColumnData::new(
+                ColumnDef::new("STARS", "4J")?,
+                self.quads.iter().map(|q| q.stars),
+            )?,
+            ColumnData::new(
+                ColumnDef::new("CODE", "4D")?,
+                self.quads.iter().map(|q| q.code),
+            )?,
+            ColumnData::new(
+                ColumnDef::new("SCALE", "D")?.with_unit("arcmin"),
+                self.quads.iter().map(|q| q.scale),
+            )?,
+        ];
+
+        let mut writer = FitsTableWriter::create(path)?;
+        writer.write_columns("STARS", &stars)?;
+        writer.write_columns("QUADS", &quads)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use common::{fits_bintable::FitsTable, sphere::radec_to_xyz, util::temp_path};
+    use serde_json::Value;
+
+    use crate::tests::build_test_index;
+
+    use super::*;
+
+    #[test]
+    fn test_region() {
+        let path = temp_path("dump.idx");
+        let index = build_test_index(&path);
+        let center = radec_to_xyz(150.0, 30.0);
+        let radius = 0.5f64.to_radians();
+
+        let dump = Dump::region(&index, &center, radius).unwrap();
+
+        let in_region = index.stars_within(&center, radius).len();
+        let xyz = |star: u32| Vector3::from(index.star_vectors()[star as usize]);
+        let quads_in_region = index
+            .quad_stars()
+            .iter()
+            .filter(|s| angular_distance(&center, &(xyz(s[0]) + xyz(s[1])).normalize()) <= radius)
+            .count();
+
+        // The region's stars, plus those of its quads reaching outside it
+        assert!(in_region > 0 && quads_in_region > 0);
+        assert!(dump.stars.len() >= in_region);
+        assert_eq!(dump.quads.len(), quads_in_region);
+        assert!(dump
+            .quads
+            .iter()
+            .all(|quad| quad.stars.iter().all(|&s| (s as usize) < dump.stars.len())));
+        assert!(dump
+            .quads
+            .iter()
+            .all(|quad| (5.0..=30.0).contains(&quad.scale)));
+
+        let fits = temp_path("dump.fits");
+        dump.write_fits(&fits).unwrap();
+
+        let stars = FitsTable::open_extension(&fits, "STARS", None).unwrap();
+        assert_eq!(stars.len(), dump.stars.len());
+        let designations = stars.columns()["DESIGNATION"].to_vec::<String>().unwrap();
+        assert_eq!(designations[0], dump.stars[0].designation);
+        let ra = stars.columns()["RA"].to_vec::<f64>().unwrap();
+        assert_eq!(ra[0], dump.stars[0].ra);
+
+        let quads = FitsTable::open_extension(&fits, "QUADS", None).unwrap();
+        assert_eq!(quads.len(), dump.quads.len());
+        assert_eq!(
+            quads.columns()["STARS"].value(0).unwrap(),
+            serde_json::json!(dump.quads[0].stars)
+        );
+
+        let json = temp_path("dump.json");
+        dump.write_json(&json).unwrap();
+        let value: Value = serde_json::from_slice(&fs::read(&json).unwrap()).unwrap();
+        assert_eq!(value["stars"].as_array().unwrap().len(), dump.stars.len());
+        assert_eq!(value["quads"].as_array().unwrap().len(), dump.quads.len());
+        assert_eq!(value["metadata"]["nside"], 64);
+
+        for path in [path, fits, json] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use common::{
+    healpix::{npix, vec2pix_nested},
+    mapped_index::MappedIndex,
+};
+use itertools::Itertools;
+use nalgebra::Vector3;
+
+const BAR_WIDTH: usize = 40;
+const SCALE_BINS: usize = 10;
+/// How many quads to measure the code-space density around
+const DENSITY_SAMPLES: usize = 1000;
+
+fn print_histogram(title: &str, bins: &[(String, usize)]) {
+    println!("\n{}", title);
+
+    let max = bins.iter().map(|(_, n)| *n).max().unwrap_or(0).max(1);
+    let width = bins.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+
+    for (label, count) in bins {
+        println!(
+            "  {:>width$} | {:<BAR_WIDTH$} {}",
+            label,
+            "#".repeat(count * BAR_WIDTH / max),
+            count
+        );
+    }
+}
+
+/// Average number of other quads within `radius` of a quad in code space.
+fn code_density(index: &MappedIndex, radius: f64) -> f64 {
+    let codes = index.quad_codes();
+    if codes.is_empty() {
+        return 0.0;
+    }
+
+    let step = (codes.len() / DENSITY_SAMPLES).max(1);
+    let samples = codes.iter().step_by(step).collect::<Vec<_>>();
+
+    let neighbours = samples
+        .iter()
+        .map(|code| index.quads_within(code, radius).len() - 1)
+        .sum::<usize>();
+
+    neighbours as f64 / samples.len() as f64
+}
+
+/// Number of cells of the index's resolution that hold each number of quads,
+/// counting only the cells the index covers. Quads belong to the cell
+/// containing the midpoint of their backbone.
+fn quads_per_cell(index: &MappedIndex) -> Result<Vec<usize>> {
+    let metadata = index.metadata();
+    let vectors = index.star_vectors();
+    let mut cells: HashMap<u64, usize> = HashMap::new();
+
+    for quad in 0..index.num_quads() {
+        let stars = index.quad_members(quad)?;
+        let [a, b] = [0, 1].map(|i| Vector3::from(vectors[stars[i]]));
+
+        *cells
+            .entry(vec2pix_nested(metadata.nside, &(a + b)))
+            .or_default() += 1;
+    }
+
+    let n_cells = match metadata.coverage() {
+        Some(tiles) => tiles
+            .iter()
+            .map(|tile| npix(metadata.nside) / npix(tile.nside))
+            .sum(),
+        None => npix(metadata.nside),
+    };
+    let max_quads = cells.values().copied().max().unwrap_or(0);
+    let mut per_cell = vec![0; max_quads + 1];
+    per_cell[0] = n_cells as usize - cells.len();
+    for &n in cells.values() {
+        per_cell[n] += 1;
+    }
+
+    Ok(per_cell)
+}
+
+/// Number of quads in each of `SCALE_BINS` equal bins of the index's scale
+/// range. Quads outside the range are counted in the first or last bin.
+fn scale_histogram(index: &MappedIndex) -> Vec<usize> {
+    let (min_scale, max_scale) = index.metadata().scale_range;
+    let bin_width = (max_scale - min_scale) / SCALE_BINS as f64;
+
+    let mut scales = vec![0; SCALE_BINS];
+    for scale in index.quad_scales() {
+        let arcmin = scale.to_degrees() * 60.0;
+        let bin = ((arcmin - min_scale) / bin_width).floor() as usize;
+        scales[bin.min(SCALE_BINS - 1)] += 1;
+    }
+
+    scales
+}
+
+pub fn print_info(index: &MappedIndex, code_radius: f64) -> Result<()> {
+    let metadata = index.metadata();
+    let (min_scale, max_scale) = metadata.scale_range;
+
+    println!("File:         {}", index.path().display());
+    print!("Checksum:     ");
+    match index.verify() {
+        Ok(()) => println!("OK"),
+        Err(e) => println!("{}", e),
+    }
+    println!(
+        "Catalog:      {} ({} band)",
+        metadata.catalog, metadata.band
+    );
+    println!("Scale range:  {}' - {}'", min_scale, max_scale);
+    println!("Nside:        {}", metadata.nside);
+    match metadata.tile {
+        Some(tile) => println!("Tile:         pixel {} at nside {}", tile.pixel, tile.nside),
+        None => println!("Tile:         whole sky"),
+    }
+    if !metadata.merged_tiles.is_empty() {
+        println!("Merged from:  {} tiles", metadata.merged_tiles.len());
+    }
+    println!("Stars:        {}", index.num_stars());
+    println!("Quads:        {}", index.num_quads());
+    println!(
+        "Code density: {:.2} quads within {} of a quad",
+        code_density(index, code_radius),
+        code_radius
+    );
+
+    let sweeps = index.star_sweeps().iter().counts();
+    print_histogram(
+        "Stars per sweep",
+        &sweeps
+            .into_iter()
+            .sorted()
+            .map(|(sweep, n)| (sweep.to_string(), n))
+            .collect::<Vec<_>>(),
+    );
+
+    let per_cell = quads_per_cell(index)?;
+    print_histogram(
+        "Cells by number of quads",
+        &per_cell
+            .into_iter()
+            .enumerate()
+            .map(|(n, cells)| (n.to_string(), cells))
+            .collect::<Vec<_>>(),
+    );
+
+    let bin_width = (max_scale - min_scale) / SCALE_BINS as f64;
+    let scales = scale_histogram(index);
+    print_histogram(
+        "Quad scales (arcminutes)",
+        &scales
+            .into_iter()
+            .enumerate()
+            .map(|(i, n)| {
+                let start = min_scale + i as f64 * bin_width;
+                (format!("{:.1} - {:.1}", start, start + bin_width), n)
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use common::{healpix::npix, util::temp_path};
+
+    use crate::tests::build_test_index;
+
+    use super::*;
+
+    #[test]
+    fn test_statistics() {
+        let path = temp_path("info.idx");
+        let index = build_test_index(&path);
+        let n_quads = index.num_quads();
+        assert!(n_quads > 0);
+
+        // No two quads share a code, and a radius spanning code space
+        // reaches all of them
+        assert_eq!(code_density(&index, 0.0), 0.0);
+        assert_eq!(code_density(&index, 10.0), (n_quads - 1) as f64);
+
+        let per_cell = quads_per_cell(&index).unwrap();
+        assert_eq!(per_cell.iter().sum::<usize>(), npix(64) as usize);
+        assert_eq!(
+            per_cell
+                .iter()
+                .enumerate()
+                .map(|(n, cells)| n * cells)
+                .sum::<usize>(),
+            n_quads
+        );
+        assert!(per_cell.len() <= 20 + 1);
+
+        let scales = scale_histogram(&index);
+        assert_eq!(scales.len(), SCALE_BINS);
+        assert_eq!(scales.iter().sum::<usize>(), n_quads);
+        // Scales from 5' to 30' fall into bins 2.5' wide
+        let in_first_bin = index
+            .quad_scales()
+            .iter()
+            .filter(|scale| scale.to_degrees() * 60.0 < 7.5)
+            .count();
+        assert_eq!(scales[0], in_first_bin);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
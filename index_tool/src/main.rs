@@ -0,0 +1,255 @@
+mod dump;
+mod info;
+mod validate;
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum};
+use common::{index_merge, mapped_index::MappedIndex, sphere::radec_to_xyz};
+use dotenvy::dotenv;
+use rand::{rngs::StdRng, SeedableRng};
+use validate::FieldParameters;
+
+/// Inspect index files
+#[derive(Parser, Debug)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Fits,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print an index's build metadata and statistics
+    Info {
+        file: PathBuf,
+        /// Radius in code space to measure the density of quads with
+        #[arg(long, default_value_t = 0.01)]
+        code_radius: f64,
+    },
+    /// Write the stars and quads in a region of the sky to a file
+    Dump {
+        file: PathBuf,
+        output: PathBuf,
+        /// Center of the region in degrees
+        #[arg(long, allow_hyphen_values = true)]
+        ra: f64,
+        #[arg(long, allow_hyphen_values = true)]
+        dec: f64,
+        /// Radius of the region in degrees
+        #[arg(long)]
+        radius: f64,
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+    },
+    /// Measure how many synthetic fields, rendered from the catalog through
+    /// random WCSs, can be recognized by looking up their quads
+    Validate {
+        file: PathBuf,
+        /// Field widths in arcminutes
+        #[arg(long, value_delimiter = ',', default_values_t = [30.0, 60.0, 120.0])]
+        field_sizes: Vec<f64>,
+        /// Number of fields per field size
+        #[arg(long, default_value_t = 100)]
+        fields: usize,
+        /// Width and height of the images in pixels
+        #[arg(long, default_value_t = 1000.0)]
+        image_size: f64,
+        /// Positional noise in pixels
+        #[arg(long, default_value_t = 1.0)]
+        sigma: f64,
+        /// Probability of a star not being detected
+        #[arg(long, default_value_t = 0.1)]
+        dropout: f64,
+        /// Spurious sources per detected star
+        #[arg(long, default_value_t = 0.2)]
+        spurious: f64,
+        /// How many of the brightest detected stars to build quads from
+        #[arg(long, default_value_t = 12)]
+        quad_stars: usize,
+        /// USNO-B .cat files or directories containing them to render the
+        /// fields from, the object DB if none are given
+        #[arg(long)]
+        catalog: Vec<PathBuf>,
+        /// Faintest catalog magnitude rendered into the fields
+        #[arg(long, default_value_t = 18.0)]
+        max_mag: f32,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Merge indexes built with the same parameters into one, which has to fit
+    /// in memory
+    Merge {
+        output: PathBuf,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Split an index into HEALPix tiles, writing one index per tile
+    Split {
+        file: PathBuf,
+        output_dir: PathBuf,
+        /// Resolution of the tiles
+        #[arg(long)]
+        nside: u32,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+
+    let args = Args::parse();
+
+    match args.command {
+        Command::Info { file, code_radius } => {
+            let index = MappedIndex::open(file)?;
+            info::print_info(&index, code_radius)?;
+        }
+        Command::Dump {
+            file,
+            output,
+            ra,
+            dec,
+            radius,
+            format,
+        } => {
+            let index = MappedIndex::open(file)?;
+            let dump = dump::Dump::region(&index, &radec_to_xyz(ra, dec), radius.to_radians())?;
+
+            println!(
+                "Writing {} stars and {} quads to {:?}",
+                dump.stars.len(),
+                dump.quads.len(),
+                output
+            );
+
+            match format {
+                Format::Json => dump.write_json(&output)?,
+                Format::Fits => dump.write_fits(&output)?,
+            }
+        }
+        Command::Validate {
+            file,
+            field_sizes,
+            fields,
+            image_size,
+            sigma,
+            dropout,
+            spurious,
+            quad_stars,
+            catalog,
+            max_mag,
+            seed,
+        } => {
+            let index = MappedIndex::open(file)?;
+            let mut rng = StdRng::seed_from_u64(seed);
+            let params = FieldParameters {
+                image_size,
+                sigma,
+                dropout,
+                spurious,
+                quad_stars,
+            };
+
+            let fields = validate::random_fields(&mut rng, &index, &field_sizes, fields);
+
+            println!("Reading catalog stars");
+            let stars = validate::read_catalog(&index, &catalog, max_mag, &fields).await?;
+
+            validate::run(&index, &stars, &field_sizes, &fields, &params, &mut rng)?;
+        }
+        Command::Merge { output, files } => {
+            let indexes = files
+                .iter()
+                .map(MappedIndex::open)
+                .collect::<Result<Vec<_>>>()?;
+            let index = index_merge::merge(&indexes)?;
+
+            println!(
+                "Writing {} stars and {} quads to {:?}",
+                index.position_index().len(),
+                index.quad_index().len(),
+                output
+            );
+            index.save(&output)?;
+        }
+        Command::Split {
+            file,
+            output_dir,
+            nside,
+        } => {
+            let index = MappedIndex::open(&file)?;
+            let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+
+            for tile in index_merge::split(&index, nside)? {
+                let pixel = tile.metadata().tile.map_or(0, |t| t.pixel);
+                let output = output_dir.join(format!("{}-{}-{}.idx", stem, nside, pixel));
+
+                println!(
+                    "Writing {} stars and {} quads to {:?}",
+                    tile.position_index().len(),
+                    tile.quad_index().len(),
+                    output
+                );
+                tile.save(&output)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use common::{index::Motion, quad_builder::QuadFilter, sphere::xyz_to_radec, star_id::StarId};
+    use index_builder::{
+        build::{build_index, BuildParameters},
+        catalog::{Band, CatalogStar},
+    };
+    use rand::Rng;
+
+    use super::*;
+
+    /// Build and map a small index of random stars around RA 150, Dec 30.
+    pub fn build_test_index(path: &Path) -> MappedIndex {
+        let mut rng = StdRng::seed_from_u64(0);
+        let center = radec_to_xyz(150.0, 30.0);
+
+        let stars = (0..500)
+            .map(|i| {
+                let point = validate::random_point_in_cap(&mut rng, &center, 1.5f64.to_radians());
+                let (ra, dec) = xyz_to_radec(&point);
+                CatalogStar {
+                    id: StarId::usnob(1200, i).unwrap(),
+                    ra,
+                    dec,
+                    mag: 10.0 + rng.gen::<f32>() * 8.0,
+                    motion: Motion::default(),
+                    sweep: 0,
+                }
+            })
+            .collect();
+
+        let params = BuildParameters {
+            nside: 64,
+            max_mag: 18.0,
+            scale_range: (5.0, 30.0),
+            uniformize_nside: 256,
+            stars_per_cell: 2,
+            quads_per_cell: 20,
+            filter: QuadFilter::default(),
+            tile: None,
+        };
+        build_index(stars, &params, Band::R).save(path).unwrap();
+
+        MappedIndex::open(path).unwrap()
+    }
+}
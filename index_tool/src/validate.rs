@@ -0,0 +1,435 @@
+use std::{f64::consts::PI, path::Path};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use common::{
+    error::AstroError,
+    flat_kdtree,
+    mapped_index::MappedIndex,
+    quad::Quad,
+    sphere::{angular_distance, chord_length, project_to_tangent_plane},
+    star_id::StarId,
+};
+use index_builder::catalog::{self, Band, CatalogStar};
+use itertools::Itertools;
+use nalgebra::Vector3;
+use rand::{rngs::StdRng, Rng};
+
+/// How to render synthetic fields
+pub struct FieldParameters {
+    /// Width and height of the square image in pixels
+    pub image_size: f64,
+    /// Standard deviation of the positional noise in pixels
+    pub sigma: f64,
+    /// Probability of an index star not being detected
+    pub dropout: f64,
+    /// Number of spurious sources per index star in the image
+    pub spurious: f64,
+    /// How many of the brightest image stars to build quads from
+    pub quad_stars: usize,
+}
+
+/// A source in a synthetic image
+struct ImageStar {
+    position: (f64, f64),
+    /// The catalog star this is an image of, None for spurious sources
+    star: Option<StarId>,
+    /// Lower is brighter
+    rank: f64,
+}
+
+/// A field to render: its center and width in radians
+pub struct Field {
+    pub center: Vector3<f64>,
+    pub size: f64,
+}
+
+impl Field {
+    /// Distance from the center to the field's corners
+    fn radius(&self) -> f64 {
+        self.size / 2.0 * 2f64.sqrt()
+    }
+}
+
+/// Catalog stars, kd-ordered so the ones in a field can be found quickly
+pub struct FieldStars {
+    stars: Vec<CatalogStar>,
+    tree: Vec<[f64; 3]>,
+}
+
+impl FieldStars {
+    pub fn new(stars: Vec<CatalogStar>) -> Self {
+        let points = stars
+            .iter()
+            .map(|s| s.xyz().into())
+            .collect::<Vec<[f64; 3]>>();
+        let order = flat_kdtree::kd_order(&points);
+
+        Self {
+            tree: order.iter().map(|&i| points[i]).collect(),
+            stars: order.into_iter().map(|i| stars[i].clone()).collect(),
+        }
+    }
+
+    fn within(&self, center: &Vector3<f64>, radius: f64) -> impl Iterator<Item = &CatalogStar> {
+        flat_kdtree::within_radius(&self.tree, &(*center).into(), chord_length(radius))
+            .into_iter()
+            .map(|i| &self.stars[i])
+    }
+}
+
+/// Outcome of trying to recognize a single field
+pub struct FieldResult {
+    pub n_stars: usize,
+    pub n_quads: usize,
+    /// Index quads matched by an image quad made of the same stars
+    pub true_matches: usize,
+    pub false_matches: usize,
+}
+
+fn gaussian(rng: &mut StdRng) -> f64 {
+    let (u1, u2) = (1.0 - rng.gen::<f64>(), rng.gen::<f64>());
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+/// A uniformly distributed point within `radius` radians of `center`.
+pub fn random_point_in_cap(rng: &mut StdRng, center: &Vector3<f64>, radius: f64) -> Vector3<f64> {
+    let z = 1.0 - rng.gen::<f64>() * (1.0 - radius.cos());
+    let phi = rng.gen::<f64>() * 2.0 * PI;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+
+    let axis = if center[2].abs() < 0.9 {
+        Vector3::z()
+    } else {
+        Vector3::x()
+    };
+    let u = center.cross(&axis).normalize();
+    let v = center.cross(&u);
+
+    (center * z + u * r * phi.cos() + v * r * phi.sin()).normalize()
+}
+
+/// Render the catalog stars in `field` into an image with a random
+/// orientation, apply noise, dropouts and spurious sources, and look up the
+/// quads of its brightest stars in the index.
+///
+/// Stars are rendered from the catalog the index was built from rather than
+/// from the index, so the fainter stars the index left out compete for the
+/// brightest image stars as they would in a real image. The image keeps the
+/// parity of the sky, so its quads' codes can be compared to the index's
+/// directly.
+pub fn validate_field(
+    rng: &mut StdRng,
+    index: &MappedIndex,
+    catalog: &FieldStars,
+    field: &Field,
+    params: &FieldParameters,
+) -> Result<FieldResult> {
+    let pixel_scale = field.size / params.image_size;
+    let angle = rng.gen::<f64>() * 2.0 * PI;
+    let (sin, cos) = angle.sin_cos();
+    let half = params.image_size / 2.0;
+    let in_image = |(x, y): (f64, f64)| {
+        (0.0..params.image_size).contains(&x) && (0.0..params.image_size).contains(&y)
+    };
+
+    let mut stars = Vec::new();
+
+    for star in catalog.within(&field.center, field.radius()) {
+        let Some((east, north)) = project_to_tangent_plane(&field.center, &star.xyz()) else {
+            continue;
+        };
+
+        let position = (
+            (cos * east - sin * north) / pixel_scale + half,
+            (sin * east + cos * north) / pixel_scale + half,
+        );
+
+        if !in_image(position) || rng.gen::<f64>() < params.dropout {
+            continue;
+        }
+
+        stars.push(ImageStar {
+            position: (
+                position.0 + params.sigma * gaussian(rng),
+                position.1 + params.sigma * gaussian(rng),
+            ),
+            star: Some(star.id),
+            rank: star.mag as f64,
+        });
+    }
+
+    // Spurious sources are as bright as the stars
+    let (min_rank, max_rank) = stars
+        .iter()
+        .map(|s| s.rank)
+        .minmax()
+        .into_option()
+        .unwrap_or((0.0, 0.0));
+    let n_spurious = (stars.len() as f64 * params.spurious).round() as usize;
+
+    for _ in 0..n_spurious {
+        stars.push(ImageStar {
+            position: (
+                rng.gen::<f64>() * params.image_size,
+                rng.gen::<f64>() * params.image_size,
+            ),
+            star: None,
+            rank: min_rank + rng.gen::<f64>() * (max_rank - min_rank),
+        });
+    }
+
+    let n_stars = stars.len();
+    stars.sort_by(|a, b| a.rank.total_cmp(&b.rank));
+    stars.truncate(params.quad_stars);
+
+    let mut result = FieldResult {
+        n_stars,
+        n_quads: 0,
+        true_matches: 0,
+        false_matches: 0,
+    };
+
+    for combination in stars.iter().combinations(4) {
+        let quad_stars = [0, 1, 2, 3].map(|i| (combination[i].position, combination[i].star));
+        let Some(quad) = Quad::new(quad_stars) else {
+            continue;
+        };
+
+        result.n_quads += 1;
+
+        let truth = quad
+            .get_stars()
+            .map(|s| s.unwrap_or(StarId::from_raw(u64::MAX)));
+        let truth = truth.into_iter().sorted().collect::<Vec<_>>();
+
+        for matched in index.matching_quads(&quad, params.sigma) {
+            let stars = index.quad_members(matched)?.map(|s| index.star_id(s));
+            if stars.into_iter().sorted().collect::<Vec<_>>() == truth {
+                result.true_matches += 1;
+            } else {
+                result.false_matches += 1;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// `n_fields` random field centers in the part of the sky `index` covers for
+/// each size in `field_sizes` (arcminutes).
+pub fn random_fields(
+    rng: &mut StdRng,
+    index: &MappedIndex,
+    field_sizes: &[f64],
+    n_fields: usize,
+) -> Vec<Field> {
+    let tile = index.metadata().tile;
+    let (center, radius) = match tile {
+        Some(tile) => (tile.center(), tile.radius()),
+        None => (Vector3::z(), PI),
+    };
+
+    let mut fields = Vec::new();
+
+    for &field_size in field_sizes {
+        for _ in 0..n_fields {
+            let center = loop {
+                let point = random_point_in_cap(rng, &center, radius);
+                if index.metadata().covers(&point) {
+                    break point;
+                }
+            };
+
+            fields.push(Field {
+                center,
+                size: (field_size / 60.0).to_radians(),
+            });
+        }
+    }
+
+    fields
+}
+
+/// Read the stars brighter than `max_mag` that lie in any of `fields` from the
+/// USNO-B catalog files in `catalog_paths`, or from the object DB if there are
+/// none, in the band the index was built for.
+pub async fn read_catalog(
+    index: &MappedIndex,
+    catalog_paths: &[impl AsRef<Path>],
+    max_mag: f32,
+    fields: &[Field],
+) -> Result<FieldStars> {
+    let band_name = &index.metadata().band;
+    let band = Band::from_str(band_name, true)
+        .map_err(|_| AstroError::new(&format!("Unknown band {}", band_name)))?;
+
+    let mut stars = Vec::new();
+    let mut visit = |star: CatalogStar| {
+        let xyz = star.xyz();
+        if fields
+            .iter()
+            .any(|field| angular_distance(&field.center, &xyz) <= field.radius())
+        {
+            stars.push(star);
+        }
+        Ok(())
+    };
+
+    if catalog_paths.is_empty() {
+        catalog::scan_database(band, max_mag, visit).await?;
+    } else {
+        catalog::scan_usnob_files(catalog_paths, band, max_mag, &mut visit)?;
+    }
+
+    Ok(FieldStars::new(stars))
+}
+
+/// Validate `index` on `fields`, as made by [`random_fields`] with `n_fields`
+/// fields of each size in `field_sizes`, and print the recall per field size.
+pub fn run(
+    index: &MappedIndex,
+    catalog: &FieldStars,
+    field_sizes: &[f64],
+    fields: &[Field],
+    params: &FieldParameters,
+    rng: &mut StdRng,
+) -> Result<()> {
+    println!(
+        "{:>10} | {:>6} | {:>10} | {:>6} | {:>5} | {:>5} | {:>13}",
+        "Field size", "Fields", "Recognized", "Recall", "Stars", "Quads", "False matches"
+    );
+
+    let n_fields = fields.len() / field_sizes.len().max(1);
+
+    for (field_size, fields) in field_sizes.iter().zip(fields.chunks(n_fields.max(1))) {
+        let mut recognized = 0;
+        let (mut stars, mut quads, mut false_matches) = (0, 0, 0);
+
+        for field in fields {
+            let result = validate_field(rng, index, catalog, field, params)?;
+
+            if result.true_matches > 0 {
+                recognized += 1;
+            }
+            stars += result.n_stars;
+            quads += result.n_quads;
+            false_matches += result.false_matches;
+        }
+
+        println!(
+            "{:>9}' | {:>6} | {:>10} | {:>5.1}% | {:>5.0} | {:>5.0} | {:>13.1}",
+            field_size,
+            n_fields,
+            recognized,
+            recognized as f64 / n_fields as f64 * 100.0,
+            stars as f64 / n_fields as f64,
+            quads as f64 / n_fields as f64,
+            false_matches as f64 / n_fields as f64,
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use common::{
+        index::Motion,
+        quad_builder::QuadFilter,
+        sphere::{radec_to_xyz, xyz_to_radec},
+        util::temp_path,
+    };
+    use index_builder::build::{build_index, BuildParameters};
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_validate_field() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let center = radec_to_xyz(150.0, 30.0);
+
+        let stars = (0..1000)
+            .map(|i| {
+                let point = random_point_in_cap(&mut rng, &center, 1.5f64.to_radians());
+                let (ra, dec) = xyz_to_radec(&point);
+                CatalogStar {
+                    id: StarId::usnob(1200, i).unwrap(),
+                    ra,
+                    dec,
+                    mag: 10.0 + rng.gen::<f32>() * 8.0,
+                    motion: Motion::default(),
+                    sweep: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let params = BuildParameters {
+            nside: 64,
+            max_mag: 18.0,
+            scale_range: (5.0, 30.0),
+            uniformize_nside: 256,
+            stars_per_cell: 2,
+            quads_per_cell: 20,
+            filter: QuadFilter::default(),
+            tile: None,
+        };
+        let path = temp_path("validate.idx");
+        build_index(stars.clone(), &params, Band::R)
+            .save(&path)
+            .unwrap();
+        let index = MappedIndex::open(&path).unwrap();
+
+        // The fields have catalog stars the index left out
+        assert!(index.num_stars() < stars.len() / 2);
+        let catalog = FieldStars::new(stars);
+
+        let params = FieldParameters {
+            image_size: 1000.0,
+            sigma: 0.5,
+            dropout: 0.0,
+            spurious: 0.0,
+            quad_stars: 12,
+        };
+        let field = Field {
+            center,
+            size: (45.0f64 / 60.0).to_radians(),
+        };
+
+        let result = validate_field(&mut rng, &index, &catalog, &field, &params).unwrap();
+        assert!(result.n_stars > params.quad_stars);
+        assert!(result.n_quads > 0);
+        assert!(result.true_matches > 0);
+
+        // Same seed, same field
+        let [a, b] = [0, 0].map(|seed| {
+            let result = validate_field(
+                &mut StdRng::seed_from_u64(seed),
+                &index,
+                &catalog,
+                &field,
+                &FieldParameters {
+                    dropout: 0.2,
+                    spurious: 0.5,
+                    ..params
+                },
+            )
+            .unwrap();
+            (result.n_stars, result.true_matches, result.false_matches)
+        });
+        assert_eq!(a, b);
+
+        // Nothing to recognize where the catalog has no stars
+        let empty = Field {
+            center: -center,
+            size: field.size,
+        };
+        let result = validate_field(&mut rng, &index, &catalog, &empty, &params).unwrap();
+        assert_eq!((result.n_stars, result.true_matches), (0, 0));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
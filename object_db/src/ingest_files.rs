@@ -1,8 +1,8 @@
 use std::io::{self, Write};
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 use anyhow::Result;
-use common::usnob::{Observation, USNOBFile};
+use common::usnob::{find_catalog_files, USNOBFile};
 
 use common::usnob::USNOBObject;
 use sqlx::{Connection, SqliteConnection};
@@ -11,53 +11,11 @@ use itertools::Itertools;
 
 use sqlx::Row;
 
-use crate::object::Object;
+use object_db::object::Object;
 
 const INSERT_BATCH_SIZE: usize = 1000;
 
 fn to_db_schema(obj: &USNOBObject, filename: &str) -> Object {
-    let bmag = {
-        let mut n = 0;
-        let mut sum = 0.0;
-
-        if let Some(Observation { mag, .. }) = obj.observations.blue1 {
-            n += 1;
-            sum += mag;
-        }
-
-        if let Some(Observation { mag, .. }) = obj.observations.blue2 {
-            n += 1;
-            sum += mag;
-        }
-
-        if n == 0 {
-            None
-        } else {
-            Some(sum / n as f32)
-        }
-    };
-
-    let rmag = {
-        let mut n = 0;
-        let mut sum = 0.0;
-        if let Some(Observation { mag, .. }) = obj.observations.red1 {
-            n += 1;
-            sum += mag;
-        }
-        if let Some(Observation { mag, .. }) = obj.observations.red2 {
-            n += 1;
-            sum += mag;
-        }
-
-        if n == 0 {
-            None
-        } else {
-            Some(sum / n as f32)
-        }
-    };
-
-    let imag = obj.observations.infrared.as_ref().map(|o| o.mag);
-
     Object {
         usnob_id: obj.usnob_id.clone(),
         ra: obj.ra,
@@ -68,37 +26,17 @@ fn to_db_schema(obj: &USNOBObject, filename: &str) -> Object {
         sigma_dec: obj.sigma_dec,
         sigma_dec_fit: obj.sigma_dec_fit,
         pm_dec: obj.pm_dec,
-        rmag,
-        bmag,
-        imag,
+        rmag: obj.observations.rmag(),
+        bmag: obj.observations.bmag(),
+        imag: obj.observations.imag(),
         epoch: obj.epoch,
         num_detections: obj.n_detections as i32,
         origin_file: filename.to_string(),
     }
 }
 
-pub fn get_files(paths: &[impl AsRef<Path>]) -> Result<Vec<PathBuf>> {
-    let mut files = Vec::new();
-
-    for path in paths {
-        let path = path.as_ref();
-        if path.is_dir() {
-            for entry in path.read_dir()? {
-                let entry = entry?;
-                if entry.path().is_file() && entry.path().extension() == Some("cat".as_ref()) {
-                    files.push(entry.path());
-                }
-            }
-        } else {
-            files.push(path.to_path_buf());
-        }
-    }
-
-    Ok(files)
-}
-
 pub async fn ingest_files(paths: &[impl AsRef<Path>]) -> Result<()> {
-    let files = get_files(paths)?;
+    let files = find_catalog_files(paths)?;
 
     let mut connection = SqliteConnection::connect(&dotenvy::var("DATABASE_URL")?).await?;
 
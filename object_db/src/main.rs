@@ -1,5 +1,4 @@
 mod ingest_files;
-mod object;
 
 use anyhow::Result;
 
@@ -1,5 +1,7 @@
-use sqlx::{query, Error, QueryBuilder, Sqlite, SqliteConnection};
+use common::star_id::{Catalog, StarId};
+use sqlx::{query, query_as, Error, FromRow, QueryBuilder, Sqlite, SqliteConnection};
 
+#[derive(FromRow)]
 pub struct Object {
     pub usnob_id: String,
     pub ra: f64,
@@ -46,6 +48,19 @@ impl Object {
         Ok(())
     }
 
+    /// Look up the catalog record of a star matched in an index, None if it
+    /// isn't in the database or not a USNO-B star.
+    pub async fn find(id: StarId, conn: &mut SqliteConnection) -> Result<Option<Object>, Error> {
+        if id.catalog() != Some(Catalog::USNOB) {
+            return Ok(None);
+        }
+
+        query_as("SELECT * FROM object WHERE usnob_id = $1")
+            .bind(id.to_string())
+            .fetch_optional(conn)
+            .await
+    }
+
     pub async fn insert_many(
         objects: impl Iterator<Item = Object>,
         conn: &mut SqliteConnection,
@@ -77,3 +92,63 @@ impl Object {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use sqlx::Connection;
+
+    use super::*;
+
+    fn object(usnob_id: &str, ra: f64, dec: f64) -> Object {
+        Object {
+            usnob_id: usnob_id.to_string(),
+            ra,
+            sigma_ra: 0.0,
+            sigma_ra_fit: 0.0,
+            pm_ra: 0.01,
+            dec,
+            sigma_dec: 0.0,
+            sigma_dec_fit: 0.0,
+            pm_dec: -0.02,
+            bmag: Some(14.5),
+            rmag: Some(13.25),
+            imag: None,
+            epoch: 1980.5,
+            num_detections: 4,
+            origin_file: "b0000.cat".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query(include_str!("../schema.sql"))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        Object::insert_many(
+            [
+                object("0000-0000065", 1.5, -89.99),
+                object("1799-0123456", 200.25, 89.9),
+            ]
+            .into_iter(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let id = "1799-0123456".parse::<StarId>().unwrap();
+        let found = Object::find(id, &mut conn).await.unwrap().unwrap();
+        assert_eq!(found.usnob_id, "1799-0123456");
+        assert_eq!((found.ra, found.dec), (200.25, 89.9));
+        assert_eq!((found.rmag, found.imag), (Some(13.25), None));
+
+        let missing = StarId::usnob(1799, 123457).unwrap();
+        assert!(Object::find(missing, &mut conn).await.unwrap().is_none());
+
+        // IDs of other catalogs are never looked up
+        let foreign = StarId::from_raw(42);
+        assert!(Object::find(foreign, &mut conn).await.unwrap().is_none());
+    }
+}